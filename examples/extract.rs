@@ -2,6 +2,7 @@ use anyhow::*;
 use structopt::StructOpt;
 use txp::*;
 
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
 #[derive(Debug, StructOpt)]
@@ -24,32 +25,46 @@ fn main() -> Result<()> {
     let mut file = File::open(&opt.input)?;
     let mut data = vec![];
     file.read_to_end(&mut data)?;
-    let (_, atlas) = TextureAtlas::parse(&data).unwrap();
     let path = opt
         .input
         .parent()
         .unwrap()
         .join(opt.input.file_stem().unwrap());
-    std::fs::create_dir(&path);
+    std::fs::create_dir_all(&path)?;
     let ext = opt.ext.unwrap_or("png".into());
-    for (i, tex) in atlas.0.into_iter().enumerate() {
+    // sprite sets start with a header of offsets instead of the signature of an atlas
+    let atlas = match TextureAtlas::from_bytes(&data) {
+        Err(txp::Error::BadMagic { .. }) => {
+            let set = SpriteSet::from_bytes(&data)?;
+            if ext != "dds" {
+                for sprite in &set.sprites {
+                    let name = format!("{}.{}", sprite.name, ext);
+                    set.sprite_image(&sprite.name)?.save(path.join(name))?;
+                }
+                return Ok(());
+            }
+            set.atlas
+        }
+        atlas => atlas?,
+    };
+    for (i, tex) in atlas.textures.into_iter().enumerate() {
         if ext == "dds" {
             let name = format!("tex{}.{}", i, ext);
             let path = path.join(name);
             let mut save = File::create(path)?;
-            let dds = tex.to_dds()?;
+            let dds = tex.to_dds(None)?;
             dds.write(&mut save)?;
         } else {
             if tex.subtextures.len() == 1 {
                 let name = format!("tex{}.{}", i, ext);
                 let path = path.join(name);
                 let t = &tex.subtextures[0].mipmaps;
-                image_extract(t[0].clone(), path);
+                image_extract(t[0].clone(), path)?;
             } else {
                 for (j, side) in tex.subtextures.iter().enumerate() {
                     let name = format!("tex{}_sub{}.{}", i, j, ext);
                     let path = path.join(name);
-                    image_extract(side.mipmaps[0].clone(), path);
+                    image_extract(side.mipmaps[0].clone(), path)?;
                 }
             }
         }
@@ -58,8 +73,10 @@ fn main() -> Result<()> {
 }
 
 use std::path::Path;
-fn image_extract<Q: AsRef<Path>>(subtex: Mipmap<'_>, path: Q) -> Option<()> {
-    let image = subtex.to_dynamic_image()?;
-    image.flipv().save(path);
-    Some(())
+/// Saves the mipmap top-down, the way the image crate expects it
+fn image_extract<Q: AsRef<Path>>(subtex: Mipmap<'_>, path: Q) -> Result<()> {
+    let image = image::DynamicImage::try_from(&subtex)
+        .map_err(|e| anyhow!("cannot convert {} to an image: {}", subtex, e))?;
+    image.save(path)?;
+    Ok(())
 }
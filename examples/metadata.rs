@@ -10,6 +10,14 @@ struct Opt {
     /// Input file
     #[structopt(parse(from_os_str))]
     input: PathBuf,
+
+    /// Texture database naming the textures
+    #[structopt(long, parse(from_os_str))]
+    tex_db: Option<PathBuf>,
+
+    /// Ids of the textures in order, as listed by the object set
+    #[structopt(long, use_delimiter = true)]
+    ids: Vec<u32>,
 }
 
 use std::fs::File;
@@ -24,9 +32,16 @@ fn main() -> Result<()> {
     let mut file = File::open(opt.input)?;
     let mut data = vec![];
     file.read_to_end(&mut data)?;
-    let (_, atlas) = TextureAtlas::parse(&data).unwrap();
-    for (i, tex) in atlas.0.iter().enumerate() {
-        println!("Texture #{}", i + 1);
+    let mut atlas = TextureAtlas::from_bytes(&data)?;
+    atlas.set_ids(opt.ids);
+    if let Some(path) = opt.tex_db {
+        TexDb::from_bytes(&std::fs::read(path)?)?.annotate(&mut atlas);
+    }
+    for (i, tex) in atlas.textures.iter().enumerate() {
+        match &tex.name {
+            Some(name) => println!("Texture #{} {}", i + 1, name),
+            None => println!("Texture #{}", i + 1),
+        }
         if tex.subtextures.len() == 1 {
             print_mips(&tex.subtextures[0].mipmaps, "\t")?;
         } else {
@@ -43,9 +58,9 @@ fn print_mips(mips: &[Mipmap<'_>], tab: &str) -> Result<()> {
     let mut tw = TabWriter::new(vec![]);
     for (i, mip) in mips.iter().enumerate() {
         // println!("\t{}", mip);
-        write!(
+        writeln!(
             tw,
-            "\t{}#{}\t{}x{}\t{:?}\n",
+            "\t{}#{}\t{}x{}\t{:?}",
             tab,
             i + 1,
             mip.width,
@@ -0,0 +1,531 @@
+use anyhow::{anyhow, bail, Context, Result};
+use structopt::StructOpt;
+use tabwriter::TabWriter;
+use txp::*;
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Inspect, extract and build txp texture atlases
+#[derive(Debug, StructOpt)]
+#[structopt(name = "txp")]
+enum Command {
+    /// Prints the textures and mipmaps of an atlas
+    Info {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        #[structopt(flatten)]
+        names: Names,
+    },
+    /// Extracts the textures of an atlas
+    Extract {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Output directory, defaults to a directory named after the input
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// `png` writes every face and level to its own image, `dds` and `ktx2` write a file per
+        /// texture
+        #[structopt(short, long, default_value = "png")]
+        format: String,
+        /// Also extract the mipmaps, not just the base level
+        #[structopt(short, long)]
+        levels: bool,
+        /// Write the rows bottom-up, the way the games store them, instead of top-down
+        #[structopt(long)]
+        bottom_up: bool,
+        #[structopt(flatten)]
+        names: Names,
+    },
+    /// Packs the images listed in `manifest.txt` into an atlas
+    ///
+    /// Every line of the manifest is `<file> [format]`, where the format defaults to DXT5.
+    /// Images are encoded with a full mipmap chain, dds and ktx2 files are imported as-is.
+    Pack {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(flatten)]
+        layout: Layout,
+        /// Only encode the base level of images
+        #[structopt(long)]
+        no_mipmaps: bool,
+        /// Read images and dds files as bottom-up, the way the games store them
+        #[structopt(long)]
+        bottom_up: bool,
+    },
+    /// Replaces a texture of an atlas, keeping its format and number of mipmaps
+    Replace {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Index of the texture to replace, starting at 0
+        index: usize,
+        /// Image, dds or ktx2 file to use as the texture
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Writes the atlas to this file instead of overwriting the input, required for archives
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Encodes the image in this format instead of the format of the old texture
+        #[structopt(short, long)]
+        format: Option<String>,
+        /// Read the image or dds file as bottom-up, the way the games store them
+        #[structopt(long)]
+        bottom_up: bool,
+    },
+    /// Rewrites an atlas with another revision or byte order
+    Convert {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(flatten)]
+        layout: Layout,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct Layout {
+    /// `classic`, or `modern` for Project DIVA F2nd and X/XHD
+    #[structopt(short, long, parse(try_from_str = parse_revision))]
+    revision: Option<Revision>,
+    /// `little`, or `big` for the PS3 games
+    #[structopt(short, long, parse(try_from_str = parse_endianness))]
+    endianness: Option<Endianness>,
+}
+
+impl Layout {
+    fn apply(&self, atlas: &mut TextureAtlas) {
+        if let Some(revision) = self.revision {
+            atlas.revision = revision;
+        }
+        if let Some(endianness) = self.endianness {
+            atlas.set_endianness(endianness);
+        }
+    }
+}
+
+// names the textures, files are then named after them. not a doc comment, structopt would use it
+// as the about text of the subcommands it is flattened into
+#[derive(Debug, StructOpt)]
+struct Names {
+    /// Texture database (`tex_db.bin`) mapping ids to names
+    #[structopt(long, parse(from_os_str))]
+    tex_db: Option<PathBuf>,
+    /// Ids of the textures in order, as listed by the object set, e.g. `0x1A2B,0x3C4D`
+    #[structopt(long, use_delimiter = true, parse(try_from_str = parse_id))]
+    ids: Vec<u32>,
+    /// Object set (`obj_*.bin`) to read the ids from instead
+    #[structopt(long, parse(from_os_str), conflicts_with = "ids")]
+    obj: Option<PathBuf>,
+}
+
+impl Names {
+    fn apply(&self, atlas: &mut TextureAtlas) -> Result<()> {
+        match &self.obj {
+            Some(path) => {
+                let obj = ObjSet::from_bytes(&read(path)?)
+                    .with_context(|| format!("failed to parse {}", path.display()))?;
+                atlas.set_ids(obj.texture_ids);
+            }
+            None => atlas.set_ids(self.ids.iter().copied()),
+        }
+        if let Some(path) = &self.tex_db {
+            let tex_db = TexDb::from_bytes(&read(path)?)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            tex_db.annotate(atlas);
+        }
+        Ok(())
+    }
+}
+
+fn parse_id(s: &str) -> Result<u32> {
+    let id = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    id.with_context(|| format!("invalid texture id `{}`", s))
+}
+
+fn parse_revision(s: &str) -> Result<Revision> {
+    match &s.to_ascii_lowercase()[..] {
+        "classic" => Ok(Revision::Classic),
+        "modern" => Ok(Revision::Modern),
+        _ => bail!("unknown revision `{}`, expected `classic` or `modern`", s),
+    }
+}
+
+fn parse_endianness(s: &str) -> Result<Endianness> {
+    match &s.to_ascii_lowercase()[..] {
+        "little" | "le" => Ok(Endianness::Little),
+        "big" | "be" => Ok(Endianness::Big),
+        _ => bail!("unknown endianness `{}`, expected `little` or `big`", s),
+    }
+}
+
+/// `--bottom-up` forces the orientation, otherwise images are top-down and textures that cannot
+/// be flipped stay bottom-up in dds files
+fn orientation(bottom_up: bool) -> Option<Orientation> {
+    bottom_up.then_some(Orientation::BottomUp)
+}
+
+fn parse_format(s: &str) -> Result<TextureFormat> {
+    use TextureFormat::*;
+    let format = match &s.to_ascii_lowercase()[..] {
+        "a8" => A8,
+        "rgb8" => RGB8,
+        "rgba8" => RGBA8,
+        "rgb5" => RGB5,
+        "rgb5a1" => RGB5A1,
+        "rgba4" => RGBA4,
+        "dxt1" | "bc1" => DXT1,
+        "dxt1a" => DXT1a,
+        "dxt3" | "bc2" => DXT3,
+        "dxt5" | "bc3" => DXT5,
+        "ati1" | "bc4" => ATI1,
+        "ati2" | "bc5" => ATI2,
+        "l8" => L8,
+        "l8a8" => L8A8,
+        "bc7" => BC7,
+        _ => bail!("unknown texture format `{}`", s),
+    };
+    Ok(format)
+}
+
+fn main() {
+    tracing_subscriber::fmt::init();
+    if let Err(e) = run(Command::from_args()) {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Info { input, names } => {
+            let file = open(&input)?;
+            let mut atlas = parse_atlas(&input, &file.data)?;
+            names.apply(&mut atlas)?;
+            info(&atlas)
+        }
+        Command::Extract {
+            input,
+            output,
+            format,
+            levels,
+            bottom_up,
+            names,
+        } => {
+            let file = open(&input)?;
+            let mut atlas = parse_atlas(&input, &file.data)?;
+            names.apply(&mut atlas)?;
+            let output = output.unwrap_or_else(|| input.with_extension(""));
+            extract(&atlas, &output, &format, levels, orientation(bottom_up))
+        }
+        Command::Pack {
+            dir,
+            output,
+            layout,
+            no_mipmaps,
+            bottom_up,
+        } => {
+            let mut atlas = pack(&dir, no_mipmaps, orientation(bottom_up))?;
+            layout.apply(&mut atlas);
+            save(&output, &atlas, None)
+        }
+        Command::Replace {
+            input,
+            index,
+            file,
+            output,
+            format,
+            bottom_up,
+        } => {
+            let src = open(&input)?;
+            if output.is_none() && src.archive.is_some() {
+                bail!(
+                    "{} is an archive, pass --output to write the new one",
+                    input.display()
+                );
+            }
+            let mut atlas = parse_atlas(&input, &src.data)?;
+            let format = format.as_deref().map(parse_format).transpose()?;
+            replace(&mut atlas, index, &file, format, orientation(bottom_up))?;
+            save(output.as_ref().unwrap_or(&input), &atlas, src.archive)
+        }
+        Command::Convert {
+            input,
+            output,
+            layout,
+        } => {
+            let src = open(&input)?;
+            let mut atlas = parse_atlas(&input, &src.data)?;
+            layout.apply(&mut atlas);
+            save(&output, &atlas, src.archive)
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// An atlas file, or the archive it was read from and the index of its entry
+struct Input {
+    data: Vec<u8>,
+    archive: Option<(Farc<'static>, usize)>,
+}
+
+/// Reads a file, picking the first entry holding an atlas if it is a FARC archive
+fn open(path: &Path) -> Result<Input> {
+    let data = read(path)?;
+    let farc = match Farc::from_bytes(&data) {
+        Ok(farc) => farc,
+        Err(Error::BadMagic { .. }) => {
+            return Ok(Input {
+                data,
+                archive: None,
+            })
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to parse {}", path.display())),
+    };
+    let index = farc
+        .entries
+        .iter()
+        .position(|x| TextureAtlas::from_bytes(&x.data).is_ok())
+        .ok_or_else(|| anyhow!("{} does not contain an atlas", path.display()))?;
+    let entries = farc
+        .entries
+        .into_iter()
+        .map(|x| FarcEntry {
+            name: x.name,
+            data: x.data.into_owned().into(),
+        })
+        .collect();
+    let farc = Farc { entries, ..farc };
+    Ok(Input {
+        data: farc.entries[index].data.to_vec(),
+        archive: Some((farc, index)),
+    })
+}
+
+fn parse_atlas<'a>(path: &Path, data: &'a [u8]) -> Result<TextureAtlas<'a>> {
+    TextureAtlas::from_bytes(data).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Writes the atlas, repacked into its archive if it was read from one
+fn save(path: &Path, atlas: &TextureAtlas, archive: Option<(Farc, usize)>) -> Result<()> {
+    let bytes = match archive {
+        Some((mut farc, index)) => {
+            farc.entries[index].data = atlas.to_bytes()?.into();
+            farc.to_bytes()
+        }
+        None => atlas.to_bytes()?,
+    };
+    fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn info(atlas: &TextureAtlas) -> Result<()> {
+    println!(
+        "{:?} revision, {:?} endian",
+        atlas.revision,
+        atlas.endianness()
+    );
+    let mut tw = TabWriter::new(std::io::stdout());
+    for (i, tex) in atlas.textures.iter().enumerate() {
+        match (&tex.name, tex.id) {
+            (Some(name), _) => writeln!(tw, "Texture #{} {}", i, name)?,
+            (None, Some(id)) => writeln!(tw, "Texture #{} {:#X}", i, id)?,
+            (None, None) => writeln!(tw, "Texture #{}", i)?,
+        }
+        for (j, subtex) in tex.subtextures.iter().enumerate() {
+            match Face::ALL.get(j).filter(|_| tex.kind == TextureKind::Cube) {
+                Some(face) => writeln!(tw, "  {:?}", face)?,
+                None if tex.subtextures.len() > 1 => writeln!(tw, "  Subtexture #{}", j)?,
+                None => {}
+            }
+            for (k, mip) in subtex.mipmaps.iter().enumerate() {
+                writeln!(
+                    tw,
+                    "    #{}\t{}x{}\t{:?}\t{} bytes",
+                    k,
+                    mip.width,
+                    mip.height,
+                    mip.format,
+                    mip.data.len()
+                )?;
+            }
+        }
+    }
+    tw.flush()?;
+    for issue in atlas.validate() {
+        println!("warning: {}", issue);
+    }
+    Ok(())
+}
+
+fn extract(
+    atlas: &TextureAtlas,
+    dir: &Path,
+    format: &str,
+    levels: bool,
+    orientation: Option<Orientation>,
+) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    for (i, tex) in atlas.textures.iter().enumerate() {
+        let stem = tex.name.clone().unwrap_or_else(|| format!("tex{}", i));
+        match format {
+            "dds" => {
+                let path = dir.join(format!("{}.dds", stem));
+                let dds = tex
+                    .to_dds(orientation)
+                    .with_context(|| format!("cannot convert texture #{} to dds", i))?;
+                let mut file = fs::File::create(&path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                dds.write(&mut file)?;
+            }
+            "ktx2" => {
+                let path = dir.join(format!("{}.ktx2", stem));
+                let ktx = tex
+                    .to_ktx2(orientation.unwrap_or_default())
+                    .with_context(|| format!("cannot convert texture #{} to ktx2", i))?;
+                fs::write(&path, ktx)
+                    .with_context(|| format!("failed to write {}", path.display()))?;
+            }
+            "png" => {
+                for (j, subtex) in tex.subtextures.iter().enumerate() {
+                    let count = if levels { subtex.mipmaps.len() } else { 1 };
+                    for (k, mip) in subtex.mipmaps.iter().take(count).enumerate() {
+                        let mut name = stem.clone();
+                        if tex.subtextures.len() > 1 {
+                            name += &format!("_sub{}", j);
+                        }
+                        if levels {
+                            name += &format!("_mip{}", k);
+                        }
+                        let path = dir.join(name + ".png");
+                        // the smallest mipmaps of the games can be zero pixels high
+                        let saved = if mip.width > 0 && mip.height > 0 {
+                            mip.save(&path, orientation.unwrap_or_default())
+                        } else {
+                            Err(Error::Undecodable("a mipmap without pixels".into()))
+                        };
+                        match saved {
+                            Err(e @ Error::Image(_)) => {
+                                return Err(e)
+                                    .with_context(|| format!("failed to write {}", path.display()))
+                            }
+                            Err(e) => {
+                                eprintln!("warning: skipping texture #{} ({}), {}", i, mip, e)
+                            }
+                            Ok(()) => {}
+                        }
+                    }
+                }
+            }
+            _ => bail!(
+                "unknown extract format `{}`, expected `png`, `dds` or `ktx2`",
+                format
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Reads an image, dds or ktx2 file into a texture, flipped from `orientation` to the way the
+/// games store textures. ktx2 files record their own orientation
+fn load_texture(
+    path: &Path,
+    format: TextureFormat,
+    max_levels: Option<u32>,
+    orientation: Option<Orientation>,
+) -> Result<Texture<'static>> {
+    let is_dds = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("dds"));
+    if is_dds {
+        let file =
+            fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let dds = ddsfile::Dds::read(file)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        return Texture::from_dds(&dds, orientation)
+            .with_context(|| format!("failed to import {}", path.display()));
+    }
+    let is_ktx2 = path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("ktx2"));
+    if is_ktx2 {
+        return Texture::from_ktx2(&read(path)?, None)
+            .with_context(|| format!("failed to import {}", path.display()));
+    }
+    let image = image::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let filter = MipFilter::default();
+    let subtex = Subtexture::generate_mipmaps(
+        &image,
+        format,
+        Quality::default(),
+        filter,
+        max_levels,
+        orientation.unwrap_or_default(),
+    )
+    .with_context(|| format!("failed to encode {}", path.display()))?;
+    Ok(Texture::new(vec![subtex]))
+}
+
+fn pack(
+    dir: &Path,
+    no_mipmaps: bool,
+    orientation: Option<Orientation>,
+) -> Result<TextureAtlas<'static>> {
+    let manifest = dir.join("manifest.txt");
+    let manifest = fs::read_to_string(&manifest)
+        .with_context(|| format!("failed to read {}", manifest.display()))?;
+    let mut textures = vec![];
+    for (n, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let file = words.next().unwrap();
+        let format = match words.next() {
+            Some(format) => {
+                parse_format(format).with_context(|| format!("manifest.txt line {}", n + 1))?
+            }
+            None => TextureFormat::DXT5,
+        };
+        let max_levels = Some(1).filter(|_| no_mipmaps);
+        textures.push(load_texture(
+            &dir.join(file),
+            format,
+            max_levels,
+            orientation,
+        )?);
+    }
+    Ok(TextureAtlas::new(textures))
+}
+
+fn replace(
+    atlas: &mut TextureAtlas,
+    index: usize,
+    file: &Path,
+    format: Option<TextureFormat>,
+    orientation: Option<Orientation>,
+) -> Result<()> {
+    let count = atlas.textures.len();
+    let old = atlas
+        .textures
+        .get(index)
+        .ok_or_else(|| anyhow!("texture #{} does not exist, the atlas has {}", index, count))?;
+    let first = old.subtextures.first().map(|x| &x.mipmaps[..]);
+    let format = format
+        .or_else(|| first.and_then(|x| x.first()).map(|x| x.format))
+        .unwrap_or(TextureFormat::DXT5);
+    let levels = first.map(|x| x.len() as u32).filter(|&x| x > 0);
+    let mut tex = load_texture(file, format, levels, orientation)?;
+    tex.set_endianness(atlas.endianness());
+    atlas.textures[index] = tex;
+    Ok(())
+}
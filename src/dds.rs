@@ -1,10 +1,10 @@
-use ddsfile;
 use ddsfile::AlphaMode;
 use ddsfile::D3D10ResourceDimension;
 use ddsfile::Dds;
 use ddsfile::NewD3dParams;
+use ddsfile::{Caps2, FourCC, MiscFlag};
 use ddsfile::{D3DFormat, DxgiFormat};
-use tracing::{debug, instrument};
+use tracing::debug;
 
 use std::convert::TryInto;
 
@@ -19,11 +19,15 @@ impl Texture<'_> {
     }
     #[tracing::instrument(skip(self))]
     fn d3d(&self) -> Result<Dds, ddsfile::Error> {
+        // legacy headers have no array layers, only cubemap faces
+        if self.kind == TextureKind::Array && self.subtextures.len() > 1 {
+            return Err(ddsfile::Error::UnsupportedFormat);
+        }
         let def = Default::default();
         let first = self
             .subtextures
-            .get(0)
-            .and_then(|x| x.mipmaps.get(0))
+            .first()
+            .and_then(|x| x.mipmaps.first())
             .unwrap_or(&def);
         let format = first
             .format
@@ -31,9 +35,9 @@ impl Texture<'_> {
             .ok_or(ddsfile::Error::UnsupportedFormat)?;
         let mipmap_levels = self
             .subtextures
-            .get(0)
+            .first()
             .and_then(|x| x.mipmaps.len().try_into().ok());
-        let caps2 = Some(Self::caps2()).filter(|_| self.subtextures.len() == 6);
+        let caps2 = Some(Self::caps2()).filter(|_| self.kind == TextureKind::Cube);
         let params = NewD3dParams {
             height: first.height,
             width: first.width,
@@ -51,18 +55,18 @@ impl Texture<'_> {
         let def = Default::default();
         let first = self
             .subtextures
-            .get(0)
-            .and_then(|x| x.mipmaps.get(0))
+            .first()
+            .and_then(|x| x.mipmaps.first())
             .unwrap_or(&def);
         let format = first.format.to_dxgi_format();
         let alpha_mode = match first.format {
             DXT1 | DXT1a => AlphaMode::PreMultiplied,
             _ => AlphaMode::Straight,
         };
-        let mipmap_levels = self.subtextures.get(0).map(|x| x.mipmaps.len() as u32);
+        let mipmap_levels = self.subtextures.first().map(|x| x.mipmaps.len() as u32);
         let array_layers = self.subtextures.len().try_into().ok().filter(|&x| x > 1);
-        let caps2 = Some(Self::caps2()).filter(|_| self.subtextures.len() == 6);
-        let is_cubemap = self.subtextures.len() == 6;
+        let caps2 = Some(Self::caps2()).filter(|_| self.kind == TextureKind::Cube);
+        let is_cubemap = self.kind == TextureKind::Cube;
         let params = ddsfile::NewDxgiParams {
             height: first.height,
             width: first.width,
@@ -86,22 +90,190 @@ impl Texture<'_> {
         );
         Dds::new_dxgi(params)
     }
+    /// Splits the data of `dds` into a subtexture for each array layer or cubemap face
+    ///
+    /// `orientation` is the orientation of `dds`, which is flipped to the way the games store
+    /// textures. `None` takes DDS files to be top-down, except for textures that cannot be flipped
+    /// by [`Mipmap::flip_vertical`], which are copied as-is.
+    ///
+    /// Mipmaps are trimmed to [`TextureFormat::unpadded_len`], so a round trip through
+    /// [`Texture::to_dds`] keeps their size.
+    #[tracing::instrument(skip(dds))]
+    pub fn from_dds(
+        dds: &Dds,
+        orientation: Option<Orientation>,
+    ) -> Result<Texture<'static>, Error> {
+        let format = TextureFormat::from_dds(dds)?;
+        if dds.get_depth() > 1 {
+            return Err(Error::Unrepresentable("volume textures".into()));
+        }
+        // only DX10 headers have array layers, ddsfile reports legacy cubemaps differently across
+        // versions so the headers are read directly
+        let (layers, cubemap) = match &dds.header10 {
+            Some(header10) => (
+                header10.array_size.max(1),
+                header10.misc_flag.contains(MiscFlag::TEXTURECUBE),
+            ),
+            None => (1, dds.header.caps2.contains(Caps2::CUBEMAP)),
+        };
+        if cubemap && layers > 1 {
+            return Err(Error::Unrepresentable("cubemap arrays".into()));
+        }
+        let layers = if cubemap { 6 } else { layers };
+        let levels = dds.get_num_mipmap_levels().max(1);
+        // the sides of a texture cannot be halved more than 32 times
+        if levels > 32 {
+            return Err(Error::CountOverflow(levels));
+        }
+        let (width, height) = (dds.get_width(), dds.get_height());
+        debug!(?format, width, height, layers, levels, cubemap);
+
+        let sizes: Vec<_> = (0..levels)
+            .map(|level| ((width >> level).max(1), (height >> level).max(1)))
+            .collect();
+        let expected = sizes
+            .iter()
+            .map(|&(width, height)| format.data_len(width, height))
+            .fold(0, usize::saturating_add)
+            .saturating_mul(layers as usize);
+        let mut data = &dds.data[..];
+        if data.len() < expected {
+            return Err(Error::TruncatedData {
+                expected,
+                available: data.len(),
+            });
+        }
+        let mut subtextures = Vec::with_capacity(layers as usize);
+        for _ in 0..layers {
+            let mut mipmaps = Vec::with_capacity(sizes.len());
+            for (level, &(width, height)) in (0..).zip(&sizes) {
+                let (level_data, rest) = data.split_at(format.data_len(width, height));
+                data = rest;
+                let mut mip = Mipmap {
+                    width,
+                    height,
+                    format,
+                    data: level_data.to_vec().into(),
+                    ..Default::default()
+                };
+                mip.set_id(level);
+                mipmaps.push(mip);
+            }
+            subtextures.push(Subtexture { mipmaps });
+        }
+        let mut tex = Texture::new(subtextures);
+        if cubemap {
+            tex.kind = TextureKind::Cube;
+        } else if layers == 6 {
+            return Err(Error::Unrepresentable("an array of 6 layers".into()));
+        }
+        tex.flip_to(orientation)?;
+        // trimmed after flipping, which pads them again
+        for mip in tex.subtextures.iter_mut().flat_map(|x| &mut x.mipmaps) {
+            let len = format.unpadded_len(mip.width, mip.height);
+            mip.data.to_mut().truncate(len);
+        }
+        Ok(tex)
+    }
+
+    /// Copies the texture into a DDS flipped to `orientation`.
+    ///
+    /// DDS files cannot record their orientation, `None` writes the texture top-down when
+    /// [`Texture::is_flippable`] and bottom-up otherwise. Asking for [`Orientation::TopDown`]
+    /// fails on textures that cannot be flipped.
     #[tracing::instrument(skip(self))]
-    pub fn to_dds(&self) -> Result<Dds, ddsfile::Error> {
+    pub fn to_dds(&self, orientation: Option<Orientation>) -> Result<Dds, ddsfile::Error> {
+        let mut flipped = Cow::Borrowed(self);
+        if orientation != Some(Orientation::BottomUp) {
+            flipped
+                .to_mut()
+                .flip_to(orientation)
+                .map_err(|e| ddsfile::Error::General(e.to_string()))?;
+        }
         let dds = self.d3d().or_else(|_| self.dxgi());
         dds.map(|mut x| {
-            x.data = self
-                .subtextures
-                .iter()
-                .flat_map(|x| x.mipmaps.iter().flat_map(|x| &x.data[..]))
-                .cloned()
-                .collect();
+            // dds needs whole blocks, see `TextureFormat::unpadded_len`
+            x.data = vec![];
+            for mip in flipped.mipmaps() {
+                let len = mip.format.data_len(mip.width.max(1), mip.height.max(1));
+                x.data.extend_from_slice(&mip.data);
+                x.data
+                    .resize(x.data.len() + len.saturating_sub(mip.data.len()), 0);
+            }
             x
         })
     }
 }
 
 impl TextureFormat {
+    /// The format of `dds`, legacy `ATI1`/`ATI2` four character codes are also recognized
+    fn from_dds(dds: &Dds) -> Result<Self, Error> {
+        let format = if let Some(format) = dds.get_dxgi_format() {
+            Self::from_dxgi_format(format).ok_or_else(|| format!("{:?}", format))
+        } else if let Some(format) = dds.get_d3d_format() {
+            Self::from_d3d_format(format).ok_or_else(|| format!("{:?}", format))
+        } else {
+            match &dds.header.spf.fourcc {
+                Some(FourCC(FourCC::ATI1)) | Some(FourCC(FourCC::BC4_UNORM)) => Ok(Self::ATI1),
+                Some(FourCC(FourCC::ATI2)) => Ok(Self::ATI2),
+                fourcc => Err(format!("{:?}", fourcc)),
+            }
+        };
+        format.map_err(Error::Unrepresentable)
+    }
+
+    /// The inverse of [`TextureFormat::to_d3d_format`], `DXT1` maps to [`TextureFormat::DXT1`]
+    #[tracing::instrument(level = "trace", ret)]
+    pub fn from_d3d_format(format: D3DFormat) -> Option<Self> {
+        use TextureFormat::*;
+        match format {
+            D3DFormat::A8 => Some(A8),
+            D3DFormat::R8G8B8 => Some(RGB8),
+            D3DFormat::A8R8G8B8 => Some(RGBA8),
+            D3DFormat::R5G6B5 => Some(RGB5),
+            D3DFormat::A1R5G5B5 => Some(RGB5A1),
+            D3DFormat::A4R4G4B4 => Some(RGBA4),
+            D3DFormat::DXT1 => Some(DXT1),
+            D3DFormat::DXT3 => Some(DXT3),
+            D3DFormat::DXT5 => Some(DXT5),
+            D3DFormat::L8 => Some(L8),
+            D3DFormat::A8L8 => Some(L8A8),
+            _ => None,
+        }
+    }
+
+    /// The inverse of [`TextureFormat::to_dxgi_format`], sRGB and typeless variants are accepted
+    #[tracing::instrument(level = "trace", ret)]
+    pub fn from_dxgi_format(format: DxgiFormat) -> Option<Self> {
+        use TextureFormat::*;
+        match format {
+            DxgiFormat::A8_UNorm => Some(A8),
+            DxgiFormat::R8G8B8A8_Typeless
+            | DxgiFormat::R8G8B8A8_UNorm
+            | DxgiFormat::R8G8B8A8_UNorm_sRGB => Some(RGBA8),
+            DxgiFormat::B5G6R5_UNorm => Some(RGB5),
+            DxgiFormat::B5G5R5A1_UNorm => Some(RGB5A1),
+            DxgiFormat::B4G4R4A4_UNorm => Some(RGBA4),
+            DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm | DxgiFormat::BC1_UNorm_sRGB => {
+                Some(DXT1)
+            }
+            DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm | DxgiFormat::BC2_UNorm_sRGB => {
+                Some(DXT3)
+            }
+            DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm | DxgiFormat::BC3_UNorm_sRGB => {
+                Some(DXT5)
+            }
+            DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => Some(ATI1),
+            DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => Some(ATI2),
+            DxgiFormat::A8P8 => Some(L8A8),
+            DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm | DxgiFormat::BC7_UNorm_sRGB => {
+                Some(BC7)
+            }
+            DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => Some(BC6H),
+            _ => None,
+        }
+    }
+
     #[tracing::instrument(level = "trace", ret)]
     pub fn to_d3d_format(&self) -> Option<D3DFormat> {
         use TextureFormat::*;
@@ -143,3 +315,175 @@ impl TextureFormat {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    fn roundtrip(tex: &Texture) {
+        for orientation in [
+            None,
+            Some(Orientation::TopDown),
+            Some(Orientation::BottomUp),
+        ] {
+            let dds = tex.to_dds(orientation).unwrap();
+            assert_eq!(&Texture::from_dds(&dds, orientation).unwrap(), tex);
+        }
+    }
+
+    #[test]
+    fn roundtrip_d3d() {
+        let atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        for tex in &atlas.textures[..3] {
+            roundtrip(&Texture::new(tex.subtextures.clone()));
+        }
+        let sub = atlas.textures[4].subtextures[0].clone();
+        assert_eq!(sub.mipmaps.len(), 8);
+        roundtrip(&Texture::new(vec![sub.clone()]));
+        let cube = Texture::new_cube([(); 6].map(|_| sub.clone()));
+        assert!(cube
+            .to_dds(Some(Orientation::BottomUp))
+            .unwrap()
+            .header10
+            .is_none());
+        roundtrip(&cube);
+        let array = Texture::new(vec![sub; 3]);
+        assert!(array
+            .to_dds(Some(Orientation::BottomUp))
+            .unwrap()
+            .header10
+            .is_some());
+        roundtrip(&array);
+    }
+
+    #[test]
+    fn roundtrip_dxgi() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        roundtrip(&atlas.textures[0]);
+    }
+
+    #[test]
+    fn unflippable() {
+        let mip = Mipmap {
+            width: 4,
+            height: 4,
+            format: TextureFormat::BC7,
+            data: (0..16).collect::<Vec<u8>>().into(),
+            ..Default::default()
+        };
+        let tex = Texture::new(vec![Subtexture { mipmaps: vec![mip] }]);
+        for orientation in [None, Some(Orientation::BottomUp)] {
+            let dds = tex.to_dds(orientation).unwrap();
+            assert_eq!(Texture::from_dds(&dds, orientation).unwrap(), tex);
+        }
+        match tex.to_dds(Some(Orientation::TopDown)) {
+            Err(ddsfile::Error::General(e)) => assert!(e.contains("cannot flip"), "{}", e),
+            e => panic!("{:?}", e.map(|_| ())),
+        }
+        let dds = tex.to_dds(None).unwrap();
+        assert_eq!(
+            Texture::from_dds(&dds, Some(Orientation::TopDown)),
+            Err(Error::Unflippable(
+                tex.subtextures[0].mipmaps[0].to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn unrepresentable() {
+        let dds = Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: DxgiFormat::R32G32B32A32_Float,
+            mipmap_levels: None,
+            array_layers: None,
+            caps2: None,
+            is_cubemap: false,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Straight,
+        })
+        .unwrap();
+        assert!(matches!(
+            Texture::from_dds(&dds, None),
+            Err(Error::Unrepresentable(_))
+        ));
+    }
+
+    fn dds(width: u32, height: u32, array_layers: Option<u32>, is_cubemap: bool) -> Dds {
+        Dds::new_dxgi(ddsfile::NewDxgiParams {
+            height,
+            width,
+            depth: None,
+            format: DxgiFormat::R8G8B8A8_UNorm,
+            mipmap_levels: None,
+            array_layers,
+            caps2: None,
+            is_cubemap,
+            resource_dimension: D3D10ResourceDimension::Texture2D,
+            alpha_mode: AlphaMode::Straight,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn layer_count() {
+        // built by hand so it does not depend on what ddsfile reports for legacy cubemaps
+        let mut legacy = Dds::new_d3d(NewD3dParams {
+            height: 4,
+            width: 4,
+            depth: None,
+            format: D3DFormat::A8R8G8B8,
+            mipmap_levels: None,
+            caps2: Some(Texture::caps2()),
+        })
+        .unwrap();
+        legacy.data = vec![0; 6 * 64];
+        let tex = Texture::from_dds(&legacy, None).unwrap();
+        assert_eq!(tex.kind, TextureKind::Cube);
+        assert_eq!(tex.subtextures.len(), 6);
+
+        let mut cube = dds(4, 4, None, true);
+        cube.header.caps2 = Caps2::empty();
+        cube.data = vec![0; 6 * 64];
+        let tex = Texture::from_dds(&cube, None).unwrap();
+        assert_eq!(tex.kind, TextureKind::Cube);
+        assert_eq!(tex.subtextures.len(), 6);
+
+        let mut array = dds(4, 4, Some(3), false);
+        array.data = vec![0; 3 * 64];
+        let tex = Texture::from_dds(&array, None).unwrap();
+        assert_eq!(tex.kind, TextureKind::Array);
+        assert_eq!(tex.subtextures.len(), 3);
+
+        let mut cube_array = dds(4, 4, Some(12), true);
+        cube_array.data = vec![0; 12 * 64];
+        assert_eq!(
+            Texture::from_dds(&cube_array, None),
+            Err(Error::Unrepresentable("cubemap arrays".into()))
+        );
+    }
+
+    #[test]
+    fn crafted_headers() {
+        let mut levels = dds(4, 4, None, false);
+        levels.header.mip_map_count = Some(40);
+        assert_eq!(
+            Texture::from_dds(&levels, None),
+            Err(Error::CountOverflow(40))
+        );
+
+        let mut layers = dds(4, 4, Some(2), false);
+        layers.header10.as_mut().unwrap().array_size = u32::MAX;
+        assert_eq!(
+            Texture::from_dds(&layers, None),
+            Err(Error::TruncatedData {
+                expected: 64 * u32::MAX as usize,
+                available: 2 * 64,
+            })
+        );
+    }
+}
@@ -0,0 +1,836 @@
+//! Software decoders for every [`TextureFormat`].
+//!
+//! Everything decodes to tightly packed RGBA8, except BC6H which decodes to RGBA32F.
+//! [`Mipmap::decode_into`] converts either to the [`PixelLayout`] the caller asks for.
+use std::convert::TryInto;
+
+use tracing::trace;
+
+use super::flip;
+use super::*;
+
+/// Decodes `data` into RGBA8, returns `None` if the data is too short or the format is BC6H
+#[tracing::instrument(level = "trace", skip(data))]
+pub(crate) fn decode_rgba8(
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Option<Vec<u8>> {
+    use TextureFormat::*;
+    if data.len() < format.data_len(width, height) {
+        trace!(len = data.len(), "data is too short");
+        return None;
+    }
+    let pixels = width as usize * height as usize;
+    let expand = |size: usize, f: fn(&[u8]) -> [u8; 4]| -> Vec<u8> {
+        data.chunks_exact(size).take(pixels).flat_map(f).collect()
+    };
+    let rgba = match format {
+        A8 => expand(1, |x| [0, 0, 0, x[0]]),
+        L8 => expand(1, |x| [x[0], x[0], x[0], 255]),
+        L8A8 => expand(2, |x| [x[0], x[0], x[0], x[1]]),
+        RGB8 => expand(3, |x| [x[0], x[1], x[2], 255]),
+        RGBA8 => data[..pixels * 4].to_vec(),
+        RGB5 => expand(2, |x| {
+            let [r, g, b] = rgb565(u16::from_le_bytes([x[0], x[1]]));
+            [r, g, b, 255]
+        }),
+        RGB5A1 => expand(2, |x| {
+            let x = u16::from_le_bytes([x[0], x[1]]);
+            let a = if x & 0x8000 != 0 { 255 } else { 0 };
+            [extend(x >> 10, 5), extend(x >> 5, 5), extend(x, 5), a]
+        }),
+        RGBA4 => expand(2, |x| {
+            let x = u16::from_le_bytes([x[0], x[1]]);
+            [
+                extend(x >> 8, 4),
+                extend(x >> 4, 4),
+                extend(x, 4),
+                extend(x >> 12, 4),
+            ]
+        }),
+        DXT1 => decode_blocks(width, height, data, 8, |b, out| bc1(b, out, false)),
+        DXT1a => decode_blocks(width, height, data, 8, |b, out| bc1(b, out, true)),
+        DXT3 => decode_blocks(width, height, data, 16, bc2),
+        DXT5 => decode_blocks(width, height, data, 16, bc3),
+        ATI1 => decode_blocks(width, height, data, 8, |b, out| {
+            let r = bc4(b);
+            for (px, r) in out.iter_mut().zip(r) {
+                *px = [r, r, r, 255];
+            }
+        }),
+        ATI2 => decode_blocks(width, height, data, 16, |b, out| {
+            let r = bc4(&b[..8]);
+            let g = bc4(&b[8..]);
+            for (i, px) in out.iter_mut().enumerate() {
+                *px = [r[i], g[i], 0, 255];
+            }
+        }),
+        BC7 => decode_blocks(width, height, data, 16, bc7),
+        BC6H => return None,
+    };
+    Some(rgba)
+}
+
+/// Decodes BC6H `data` into RGBA32F, returns `None` if the data is too short
+#[tracing::instrument(level = "trace", skip(data))]
+pub(crate) fn decode_rgba32f(width: u32, height: u32, data: &[u8]) -> Option<Vec<f32>> {
+    if data.len() < TextureFormat::BC6H.data_len(width, height) {
+        return None;
+    }
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![1.0; width * height * 4];
+    let blocks_x = width.div_ceil(4);
+    for (i, block) in data.chunks_exact(16).enumerate() {
+        let (bx, by) = (i % blocks_x * 4, i / blocks_x * 4);
+        if by >= height {
+            break;
+        }
+        let texels = bc6h(block);
+        for (j, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + j % 4, by + j / 4);
+            if x < width && y < height {
+                let idx = (y * width + x) * 4;
+                for c in 0..3 {
+                    rgba[idx + c] = half_to_f32(texel[c]);
+                }
+            }
+        }
+    }
+    Some(rgba)
+}
+
+impl PixelLayout {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelLayout::Rgba8 | PixelLayout::Bgra8 => 4,
+            PixelLayout::Rgba32F => 16,
+        }
+    }
+}
+
+impl Mipmap<'_> {
+    /// Decodes the mipmap into top-down RGBA8, BC6H is clamped to 0-1
+    pub fn decode_rgba8(&self) -> Result<Vec<u8>, Error> {
+        let mut rgba = vec![0; self.width as usize * self.height as usize * 4];
+        self.decode_into(&mut rgba, PixelLayout::Rgba8, Orientation::default())?;
+        Ok(rgba)
+    }
+
+    /// Decodes the mipmap into the start of `out`, which has to hold `width * height` pixels.
+    ///
+    /// Mipmaps shorter than whole blocks, see [`TextureFormat::unpadded_len`], are decoded as if
+    /// they were padded with zeroes.
+    #[tracing::instrument(level = "trace", skip(self, out), fields(%self))]
+    pub fn decode_into(
+        &self,
+        out: &mut [u8],
+        layout: PixelLayout,
+        orientation: Orientation,
+    ) -> Result<(), Error> {
+        let (width, height) = (self.width, self.height);
+        let expected = width as usize * height as usize * layout.bytes_per_pixel();
+        if out.len() < expected {
+            return Err(Error::BufferTooSmall {
+                expected,
+                available: out.len(),
+            });
+        }
+        let len = self.format.data_len(width, height);
+        let mut data = Cow::Borrowed(&self.data[..]);
+        if data.len() < len && data.len() >= self.format.unpadded_len(width, height) {
+            data.to_mut().resize(len, 0);
+        }
+        let truncated = || Error::TruncatedData {
+            expected: len,
+            available: self.data.len(),
+        };
+        let out = &mut out[..expected];
+
+        if self.format == TextureFormat::BC6H {
+            let rgba = decode_rgba32f(width, height, &data).ok_or_else(truncated)?;
+            let unorm = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+            match layout {
+                PixelLayout::Rgba32F => {
+                    for (out, x) in out.chunks_exact_mut(4).zip(rgba) {
+                        out.copy_from_slice(&x.to_ne_bytes());
+                    }
+                }
+                PixelLayout::Rgba8 | PixelLayout::Bgra8 => {
+                    for (out, x) in out.iter_mut().zip(rgba) {
+                        *out = unorm(x);
+                    }
+                }
+            }
+        } else {
+            let rgba = decode_rgba8(self.format, width, height, &data).ok_or_else(truncated)?;
+            match layout {
+                PixelLayout::Rgba32F => {
+                    for (out, x) in out.chunks_exact_mut(4).zip(rgba) {
+                        out.copy_from_slice(&(x as f32 / 255.0).to_ne_bytes());
+                    }
+                }
+                PixelLayout::Rgba8 | PixelLayout::Bgra8 => out.copy_from_slice(&rgba),
+            }
+        }
+        if layout == PixelLayout::Bgra8 {
+            for px in out.chunks_exact_mut(4) {
+                px.swap(0, 2);
+            }
+        }
+        if orientation == Orientation::TopDown {
+            flip::flip_rows(out, width as usize * layout.bytes_per_pixel());
+        }
+        Ok(())
+    }
+}
+
+fn decode_blocks<F>(width: u32, height: u32, data: &[u8], size: usize, mut f: F) -> Vec<u8>
+where
+    F: FnMut(&[u8], &mut [[u8; 4]; 16]),
+{
+    let (width, height) = (width as usize, height as usize);
+    let mut rgba = vec![0; width * height * 4];
+    let blocks_x = width.div_ceil(4);
+    let mut texels = [[0; 4]; 16];
+    for (i, block) in data.chunks_exact(size).enumerate() {
+        let (bx, by) = (i % blocks_x * 4, i / blocks_x * 4);
+        if by >= height {
+            break;
+        }
+        f(block, &mut texels);
+        for (j, texel) in texels.iter().enumerate() {
+            let (x, y) = (bx + j % 4, by + j / 4);
+            if x < width && y < height {
+                let idx = (y * width + x) * 4;
+                rgba[idx..idx + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    rgba
+}
+
+/// Replicates the top bits of a `bits` wide value to 8 bits
+pub(crate) fn extend(x: u16, bits: u32) -> u8 {
+    let x = (x & ((1 << bits) - 1)) as u32;
+    ((x << (8 - bits)) | (x >> (2 * bits - 8).min(bits))) as u8
+}
+
+fn rgb565(x: u16) -> [u8; 3] {
+    [extend(x >> 11, 5), extend(x >> 5, 6), extend(x, 5)]
+}
+
+pub(crate) fn bc1_palette(block: &[u8], four_colors: bool, punchthrough: bool) -> [[u8; 4]; 4] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let [r0, g0, b0] = rgb565(c0).map(u32::from);
+    let [r1, g1, b1] = rgb565(c1).map(u32::from);
+    let mix = |a: u32, b: u32, wa: u32, wb: u32| ((a * wa + b * wb) / (wa + wb)) as u8;
+    let c0 = [r0 as u8, g0 as u8, b0 as u8, 255];
+    let c1 = [r1 as u8, g1 as u8, b1 as u8, 255];
+    if four_colors {
+        let c2 = [mix(r0, r1, 2, 1), mix(g0, g1, 2, 1), mix(b0, b1, 2, 1), 255];
+        let c3 = [mix(r0, r1, 1, 2), mix(g0, g1, 1, 2), mix(b0, b1, 1, 2), 255];
+        [c0, c1, c2, c3]
+    } else {
+        let c2 = [mix(r0, r1, 1, 1), mix(g0, g1, 1, 1), mix(b0, b1, 1, 1), 255];
+        let c3 = [0, 0, 0, if punchthrough { 0 } else { 255 }];
+        [c0, c1, c2, c3]
+    }
+}
+
+fn bc1_with(block: &[u8], out: &mut [[u8; 4]; 16], four_colors: bool, punchthrough: bool) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = bc1_palette(block, four_colors || c0 > c1, punchthrough);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, px) in out.iter_mut().enumerate() {
+        *px = palette[(indices >> (2 * i) & 3) as usize];
+    }
+}
+
+fn bc1(block: &[u8], out: &mut [[u8; 4]; 16], punchthrough: bool) {
+    bc1_with(block, out, false, punchthrough)
+}
+
+fn bc2(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    bc1_with(&block[8..], out, true, false);
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    for (i, px) in out.iter_mut().enumerate() {
+        px[3] = extend((alpha >> (4 * i)) as u16, 4);
+    }
+}
+
+fn bc3(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    bc1_with(&block[8..], out, true, false);
+    let alpha = bc4(&block[..8]);
+    for (px, a) in out.iter_mut().zip(alpha) {
+        px[3] = a;
+    }
+}
+
+pub(crate) fn bc4_palette(a0: u8, a1: u8) -> [u8; 8] {
+    let (a0, a1) = (a0 as u32, a1 as u32);
+    let mut palette = [a0 as u8, a1 as u8, 0, 0, 0, 0, 0, 255];
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn bc4(block: &[u8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let mut out = [0; 16];
+    for (i, x) in out.iter_mut().enumerate() {
+        *x = palette[(indices >> (3 * i) & 7) as usize];
+    }
+    out
+}
+
+/// Reads bits from a 128-bit block, least significant bit first
+struct Bits(u128);
+
+impl Bits {
+    fn new(block: &[u8]) -> Self {
+        Self(u128::from_le_bytes(block[..16].try_into().unwrap()))
+    }
+
+    fn read(&mut self, n: u32) -> u32 {
+        let x = (self.0 & ((1 << n) - 1)) as u32;
+        self.0 >>= n;
+        x
+    }
+
+    fn bit(&mut self) -> u32 {
+        self.read(1)
+    }
+}
+
+pub(crate) const WEIGHTS2: [u32; 4] = [0, 21, 43, 64];
+pub(crate) const WEIGHTS3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+pub(crate) const WEIGHTS4: [u32; 16] =
+    [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &WEIGHTS2,
+        3 => &WEIGHTS3,
+        _ => &WEIGHTS4,
+    }
+}
+
+pub(crate) fn interpolate(e0: u32, e1: u32, weight: u32) -> u32 {
+    ((64 - weight) * e0 + weight * e1 + 32) >> 6
+}
+
+/// Subset of each texel for the two subset partitions, as a bitmask
+pub(crate) const PARTITIONS2: [u16; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+#[rustfmt::skip]
+const PARTITIONS3: [[u8; 16]; 64] = [
+    [0,0,1,1,0,0,1,1,0,2,2,1,2,2,2,2], [0,0,0,1,0,0,1,1,2,2,1,1,2,2,2,1],
+    [0,0,0,0,2,0,0,1,2,2,1,1,2,2,1,1], [0,2,2,2,0,0,2,2,0,0,1,1,0,1,1,1],
+    [0,0,0,0,0,0,0,0,1,1,2,2,1,1,2,2], [0,0,1,1,0,0,1,1,0,0,2,2,0,0,2,2],
+    [0,0,2,2,0,0,2,2,1,1,1,1,1,1,1,1], [0,0,1,1,0,0,1,1,2,2,1,1,2,2,1,1],
+    [0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2], [0,0,0,0,1,1,1,1,1,1,1,1,2,2,2,2],
+    [0,0,0,0,1,1,1,1,2,2,2,2,2,2,2,2], [0,0,1,2,0,0,1,2,0,0,1,2,0,0,1,2],
+    [0,1,1,2,0,1,1,2,0,1,1,2,0,1,1,2], [0,1,2,2,0,1,2,2,0,1,2,2,0,1,2,2],
+    [0,0,1,1,0,1,1,2,1,1,2,2,1,2,2,2], [0,0,1,1,2,0,0,1,2,2,0,0,2,2,2,0],
+    [0,0,0,1,0,0,1,1,0,1,1,2,1,1,2,2], [0,1,1,1,0,0,1,1,2,0,0,1,2,2,0,0],
+    [0,0,0,0,1,1,2,2,1,1,2,2,1,1,2,2], [0,0,2,2,0,0,2,2,0,0,2,2,1,1,1,1],
+    [0,1,1,1,0,1,1,1,0,2,2,2,0,2,2,2], [0,0,0,1,0,0,0,1,2,2,2,1,2,2,2,1],
+    [0,0,0,0,0,0,1,1,0,1,2,2,0,1,2,2], [0,0,0,0,1,1,0,0,2,2,1,0,2,2,1,0],
+    [0,1,2,2,0,1,2,2,0,0,1,1,0,0,0,0], [0,0,1,2,0,0,1,2,1,1,2,2,2,2,2,2],
+    [0,1,1,0,1,2,2,1,1,2,2,1,0,1,1,0], [0,0,0,0,0,1,1,0,1,2,2,1,1,2,2,1],
+    [0,0,2,2,1,1,0,2,1,1,0,2,0,0,2,2], [0,1,1,0,0,1,1,0,2,0,0,2,2,2,2,2],
+    [0,0,1,1,0,1,2,2,0,1,2,2,0,0,1,1], [0,0,0,0,2,0,0,0,2,2,1,1,2,2,2,1],
+    [0,0,0,0,0,0,0,2,1,1,2,2,1,2,2,2], [0,2,2,2,0,0,2,2,0,0,1,2,0,0,1,1],
+    [0,0,1,1,0,0,1,2,0,0,2,2,0,2,2,2], [0,1,2,0,0,1,2,0,0,1,2,0,0,1,2,0],
+    [0,0,0,0,1,1,1,1,2,2,2,2,0,0,0,0], [0,1,2,0,1,2,0,1,2,0,1,2,0,1,2,0],
+    [0,1,2,0,2,0,1,2,1,2,0,1,0,1,2,0], [0,0,1,1,2,2,0,0,1,1,2,2,0,0,1,1],
+    [0,0,1,1,1,1,2,2,2,2,0,0,0,0,1,1], [0,1,0,1,0,1,0,1,2,2,2,2,2,2,2,2],
+    [0,0,0,0,0,0,0,0,2,1,2,1,2,1,2,1], [0,0,2,2,1,1,2,2,0,0,2,2,1,1,2,2],
+    [0,0,2,2,0,0,1,1,0,0,2,2,0,0,1,1], [0,2,2,0,1,2,2,1,0,2,2,0,1,2,2,1],
+    [0,1,0,1,2,2,2,2,2,2,2,2,0,1,0,1], [0,0,0,0,2,1,2,1,2,1,2,1,2,1,2,1],
+    [0,1,0,1,0,1,0,1,0,1,0,1,2,2,2,2], [0,2,2,2,0,1,1,1,0,2,2,2,0,1,1,1],
+    [0,0,0,2,1,1,1,2,0,0,0,2,1,1,1,2], [0,0,0,0,2,1,1,2,2,1,1,2,2,1,1,2],
+    [0,2,2,2,0,1,1,1,0,1,1,1,0,2,2,2], [0,0,0,2,1,1,1,2,1,1,1,2,0,0,0,2],
+    [0,1,1,0,0,1,1,0,0,1,1,0,2,2,2,2], [0,0,0,0,0,0,0,0,2,1,1,2,2,1,1,2],
+    [0,1,1,0,0,1,1,0,2,2,2,2,2,2,2,2], [0,0,2,2,0,0,1,1,0,0,1,1,0,0,2,2],
+    [0,0,2,2,1,1,2,2,1,1,2,2,0,0,2,2], [0,0,0,0,0,0,0,0,0,0,0,0,2,1,1,2],
+    [0,0,0,2,0,0,0,1,0,0,0,2,0,0,0,1], [0,2,2,2,1,2,2,2,0,2,2,2,1,2,2,2],
+    [0,1,0,1,2,2,2,2,2,2,2,2,2,2,2,2], [0,1,1,1,2,0,1,1,2,2,0,1,2,2,2,0],
+];
+
+/// Anchor texel of the second subset of the two subset partitions
+#[rustfmt::skip]
+const ANCHORS2: [u8; 64] = [
+    15,15,15,15,15,15,15,15, 15,15,15,15,15,15,15,15,
+    15, 2, 8, 2, 2, 8, 8,15,  2, 8, 2, 2, 8, 8, 2, 2,
+    15,15, 6, 8, 2, 8,15,15,  2, 8, 2, 2, 2,15,15, 6,
+     6, 2, 6, 8,15,15, 2, 2, 15,15,15,15,15, 2, 2,15,
+];
+
+/// Anchor texels of the second and third subsets of the three subset partitions
+#[rustfmt::skip]
+const ANCHORS3: [[u8; 64]; 2] = [
+    [
+         3, 3,15,15, 8, 3,15,15,  8, 8, 6, 6, 6, 5, 3, 3,
+         3, 3, 8,15, 3, 3, 6,10,  5, 8, 8, 6, 8, 5,15,15,
+         8,15, 3, 5, 6,10, 8,15, 15, 3,15, 5,15,15,15,15,
+         3,15, 5, 5, 5, 8, 5,10,  5,10, 8,13,15,12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3,15,15, 3, 8, 15,15,15,15,15,15,15, 8,
+        15, 8,15, 3,15, 8,15, 8,  3,15, 6,10,15,15,10, 8,
+        15, 3,15,10,10, 8, 9,10,  6,15, 8,15, 3, 6, 6, 8,
+        15, 3,15,15,15,15,15,15, 15,15,15,15, 3,15,15, 8,
+    ],
+];
+
+/// Returns the subset of every texel and whether it is an anchor texel
+fn partition(subsets: u32, index: usize) -> ([u8; 16], [bool; 16]) {
+    let mut subset = [0; 16];
+    let mut anchor = [false; 16];
+    anchor[0] = true;
+    match subsets {
+        2 => {
+            for (i, s) in subset.iter_mut().enumerate() {
+                *s = (PARTITIONS2[index] >> i & 1) as u8;
+            }
+            anchor[ANCHORS2[index] as usize] = true;
+        }
+        3 => {
+            subset = PARTITIONS3[index];
+            anchor[ANCHORS3[0][index] as usize] = true;
+            anchor[ANCHORS3[1][index] as usize] = true;
+        }
+        _ => {}
+    }
+    (subset, anchor)
+}
+
+struct Bc7Mode {
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    index_bits2: u32,
+}
+
+#[rustfmt::skip]
+const BC7_MODES: [Bc7Mode; 8] = [
+    Bc7Mode { subsets: 3, partition_bits: 4, rotation_bits: 0, index_selection_bits: 0, color_bits: 4, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 6, alpha_bits: 0, endpoint_pbits: false, shared_pbits: true, index_bits: 3, index_bits2: 0 },
+    Bc7Mode { subsets: 3, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 0, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 0, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 1, color_bits: 5, alpha_bits: 6, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 3 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 2, index_selection_bits: 0, color_bits: 7, alpha_bits: 8, endpoint_pbits: false, shared_pbits: false, index_bits: 2, index_bits2: 2 },
+    Bc7Mode { subsets: 1, partition_bits: 0, rotation_bits: 0, index_selection_bits: 0, color_bits: 7, alpha_bits: 7, endpoint_pbits: true, shared_pbits: false, index_bits: 4, index_bits2: 0 },
+    Bc7Mode { subsets: 2, partition_bits: 6, rotation_bits: 0, index_selection_bits: 0, color_bits: 5, alpha_bits: 5, endpoint_pbits: true, shared_pbits: false, index_bits: 2, index_bits2: 0 },
+];
+
+fn bc7(block: &[u8], out: &mut [[u8; 4]; 16]) {
+    let Some(mode) = (0..8).find(|i| block[0] >> i & 1 == 1) else {
+        *out = [[0; 4]; 16];
+        return;
+    };
+    let m = &BC7_MODES[mode];
+    let mut bits = Bits::new(block);
+    bits.read(mode as u32 + 1);
+    let partition_index = bits.read(m.partition_bits) as usize;
+    let rotation = bits.read(m.rotation_bits);
+    let index_selection = bits.read(m.index_selection_bits);
+
+    let endpoints = 2 * m.subsets as usize;
+    let mut colors = [[0u32; 4]; 6];
+    for c in 0..3 {
+        for color in colors.iter_mut().take(endpoints) {
+            color[c] = bits.read(m.color_bits);
+        }
+    }
+    for color in colors.iter_mut().take(endpoints) {
+        color[3] = if m.alpha_bits > 0 {
+            bits.read(m.alpha_bits)
+        } else {
+            255
+        };
+    }
+
+    let mut color_bits = m.color_bits;
+    let mut alpha_bits = m.alpha_bits;
+    if m.endpoint_pbits || m.shared_pbits {
+        let mut pbits = [0; 6];
+        if m.endpoint_pbits {
+            for p in pbits.iter_mut().take(endpoints) {
+                *p = bits.bit();
+            }
+        } else {
+            for s in 0..m.subsets as usize {
+                let p = bits.bit();
+                pbits[2 * s] = p;
+                pbits[2 * s + 1] = p;
+            }
+        }
+        for (color, p) in colors.iter_mut().zip(pbits).take(endpoints) {
+            for c in color.iter_mut().take(if m.alpha_bits > 0 { 4 } else { 3 }) {
+                *c = *c << 1 | p;
+            }
+        }
+        color_bits += 1;
+        if m.alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    for color in colors.iter_mut().take(endpoints) {
+        for c in color.iter_mut().take(3) {
+            *c = extend(*c as u16, color_bits) as u32;
+        }
+        if m.alpha_bits > 0 {
+            color[3] = extend(color[3] as u16, alpha_bits) as u32;
+        }
+    }
+
+    let (subset, anchor) = partition(m.subsets, partition_index);
+    let mut indices = [0; 16];
+    for (i, x) in indices.iter_mut().enumerate() {
+        *x = bits.read(m.index_bits - anchor[i] as u32);
+    }
+    let mut indices2 = [0; 16];
+    if m.index_bits2 > 0 {
+        for (i, x) in indices2.iter_mut().enumerate() {
+            *x = bits.read(m.index_bits2 - (i == 0) as u32);
+        }
+    }
+
+    for (i, px) in out.iter_mut().enumerate() {
+        let s = subset[i] as usize;
+        let (e0, e1) = (colors[2 * s], colors[2 * s + 1]);
+        let (color_index, color_bits, alpha_index, alpha_bits) = if m.index_bits2 == 0 {
+            (indices[i], m.index_bits, indices[i], m.index_bits)
+        } else if index_selection == 0 {
+            (indices[i], m.index_bits, indices2[i], m.index_bits2)
+        } else {
+            (indices2[i], m.index_bits2, indices[i], m.index_bits)
+        };
+        let cw = weights(color_bits)[color_index as usize];
+        let aw = weights(alpha_bits)[alpha_index as usize];
+        let mut rgba = [
+            interpolate(e0[0], e1[0], cw) as u8,
+            interpolate(e0[1], e1[1], cw) as u8,
+            interpolate(e0[2], e1[2], cw) as u8,
+            interpolate(e0[3], e1[3], aw) as u8,
+        ];
+        if rotation > 0 {
+            rgba.swap(rotation as usize - 1, 3);
+        }
+        *px = rgba;
+    }
+}
+
+/// Where the bits of a BC6H mode header go, as `(endpoint, channel, bit, count)`.
+///
+/// Endpoints are numbered as `w0 = 0`, `w1 = 1`, `x0 = 2`... for the (r, g, b) channels.
+/// A negative count reads the bits in reverse order.
+type Bc6hField = (u8, u8, u8, i8);
+
+struct Bc6hMode {
+    transformed: bool,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    two_regions: bool,
+    fields: &'static [Bc6hField],
+}
+
+const R: u8 = 0;
+const G: u8 = 1;
+const B: u8 = 2;
+
+#[rustfmt::skip]
+const BC6H_MODES: [(u32, Bc6hMode); 14] = [
+    (0b00, Bc6hMode { transformed: true, endpoint_bits: 10, delta_bits: [5, 5, 5], two_regions: true, fields: &[
+        (2, G, 4, 1), (2, B, 4, 1), (3, B, 4, 1), (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10),
+        (1, R, 0, 5), (3, G, 4, 1), (2, G, 0, 4), (1, G, 0, 5), (3, B, 0, 1), (3, G, 0, 4),
+        (1, B, 0, 5), (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 5), (3, B, 2, 1), (3, R, 0, 5), (3, B, 3, 1),
+    ] }),
+    (0b01, Bc6hMode { transformed: true, endpoint_bits: 7, delta_bits: [6, 6, 6], two_regions: true, fields: &[
+        (2, G, 5, 1), (3, G, 4, 1), (3, G, 5, 1), (0, R, 0, 7), (3, B, 0, 1), (3, B, 1, 1), (2, B, 4, 1),
+        (0, G, 0, 7), (2, B, 5, 1), (3, B, 2, 1), (2, G, 4, 1), (0, B, 0, 7), (3, B, 3, 1), (3, B, 5, 1),
+        (3, B, 4, 1), (1, R, 0, 6), (2, G, 0, 4), (1, G, 0, 6), (3, G, 0, 4), (1, B, 0, 6), (2, B, 0, 4),
+        (2, R, 0, 6), (3, R, 0, 6),
+    ] }),
+    (0b00010, Bc6hMode { transformed: true, endpoint_bits: 11, delta_bits: [5, 4, 4], two_regions: true, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 5), (0, R, 10, 1), (2, G, 0, 4),
+        (1, G, 0, 4), (0, G, 10, 1), (3, B, 0, 1), (3, G, 0, 4), (1, B, 0, 4), (0, B, 10, 1),
+        (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 5), (3, B, 2, 1), (3, R, 0, 5), (3, B, 3, 1),
+    ] }),
+    (0b00110, Bc6hMode { transformed: true, endpoint_bits: 11, delta_bits: [4, 5, 4], two_regions: true, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 4), (0, R, 10, 1), (3, G, 4, 1),
+        (2, G, 0, 4), (1, G, 0, 5), (0, G, 10, 1), (3, G, 0, 4), (1, B, 0, 4), (0, B, 10, 1),
+        (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 4), (3, B, 0, 1), (3, B, 2, 1), (3, R, 0, 4),
+        (2, G, 4, 1), (3, B, 3, 1),
+    ] }),
+    (0b01010, Bc6hMode { transformed: true, endpoint_bits: 11, delta_bits: [4, 4, 5], two_regions: true, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 4), (0, R, 10, 1), (2, B, 4, 1),
+        (2, G, 0, 4), (1, G, 0, 4), (0, G, 10, 1), (3, B, 0, 1), (3, G, 0, 4), (1, B, 0, 5),
+        (0, B, 10, 1), (2, B, 0, 4), (2, R, 0, 4), (3, B, 1, 1), (3, B, 2, 1), (3, R, 0, 4),
+        (3, B, 4, 1), (3, B, 3, 1),
+    ] }),
+    (0b01110, Bc6hMode { transformed: true, endpoint_bits: 9, delta_bits: [5, 5, 5], two_regions: true, fields: &[
+        (0, R, 0, 9), (2, B, 4, 1), (0, G, 0, 9), (2, G, 4, 1), (0, B, 0, 9), (3, B, 4, 1),
+        (1, R, 0, 5), (3, G, 4, 1), (2, G, 0, 4), (1, G, 0, 5), (3, B, 0, 1), (3, G, 0, 4),
+        (1, B, 0, 5), (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 5), (3, B, 2, 1), (3, R, 0, 5), (3, B, 3, 1),
+    ] }),
+    (0b10010, Bc6hMode { transformed: true, endpoint_bits: 8, delta_bits: [6, 5, 5], two_regions: true, fields: &[
+        (0, R, 0, 8), (3, G, 4, 1), (2, B, 4, 1), (0, G, 0, 8), (3, B, 2, 1), (2, G, 4, 1),
+        (0, B, 0, 8), (3, B, 3, 1), (3, B, 4, 1), (1, R, 0, 6), (2, G, 0, 4), (1, G, 0, 5),
+        (3, B, 0, 1), (3, G, 0, 4), (1, B, 0, 5), (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 6), (3, R, 0, 6),
+    ] }),
+    (0b10110, Bc6hMode { transformed: true, endpoint_bits: 8, delta_bits: [5, 6, 5], two_regions: true, fields: &[
+        (0, R, 0, 8), (3, B, 0, 1), (2, B, 4, 1), (0, G, 0, 8), (2, G, 5, 1), (2, G, 4, 1),
+        (0, B, 0, 8), (3, G, 5, 1), (3, B, 4, 1), (1, R, 0, 5), (3, G, 4, 1), (2, G, 0, 4),
+        (1, G, 0, 6), (3, G, 0, 4), (1, B, 0, 5), (3, B, 1, 1), (2, B, 0, 4), (2, R, 0, 5),
+        (3, B, 2, 1), (3, R, 0, 5), (3, B, 3, 1),
+    ] }),
+    (0b11010, Bc6hMode { transformed: true, endpoint_bits: 8, delta_bits: [5, 5, 6], two_regions: true, fields: &[
+        (0, R, 0, 8), (3, B, 1, 1), (2, B, 4, 1), (0, G, 0, 8), (2, B, 5, 1), (2, G, 4, 1),
+        (0, B, 0, 8), (3, B, 5, 1), (3, B, 4, 1), (1, R, 0, 5), (3, G, 4, 1), (2, G, 0, 4),
+        (1, G, 0, 5), (3, B, 0, 1), (3, G, 0, 4), (1, B, 0, 6), (2, B, 0, 4), (2, R, 0, 5),
+        (3, B, 2, 1), (3, R, 0, 5), (3, B, 3, 1),
+    ] }),
+    (0b11110, Bc6hMode { transformed: false, endpoint_bits: 6, delta_bits: [6, 6, 6], two_regions: true, fields: &[
+        (0, R, 0, 6), (3, G, 4, 1), (3, B, 0, 1), (3, B, 1, 1), (2, B, 4, 1), (0, G, 0, 6),
+        (2, G, 5, 1), (2, B, 5, 1), (3, B, 2, 1), (2, G, 4, 1), (0, B, 0, 6), (3, G, 5, 1),
+        (3, B, 3, 1), (3, B, 5, 1), (3, B, 4, 1), (1, R, 0, 6), (2, G, 0, 4), (1, G, 0, 6),
+        (3, G, 0, 4), (1, B, 0, 6), (2, B, 0, 4), (2, R, 0, 6), (3, R, 0, 6),
+    ] }),
+    (0b00011, Bc6hMode { transformed: false, endpoint_bits: 10, delta_bits: [10, 10, 10], two_regions: false, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 10), (1, G, 0, 10), (1, B, 0, 10),
+    ] }),
+    (0b00111, Bc6hMode { transformed: true, endpoint_bits: 11, delta_bits: [9, 9, 9], two_regions: false, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 9), (0, R, 10, 1), (1, G, 0, 9),
+        (0, G, 10, 1), (1, B, 0, 9), (0, B, 10, 1),
+    ] }),
+    (0b01011, Bc6hMode { transformed: true, endpoint_bits: 12, delta_bits: [8, 8, 8], two_regions: false, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 8), (0, R, 10, -2), (1, G, 0, 8),
+        (0, G, 10, -2), (1, B, 0, 8), (0, B, 10, -2),
+    ] }),
+    (0b01111, Bc6hMode { transformed: true, endpoint_bits: 16, delta_bits: [4, 4, 4], two_regions: false, fields: &[
+        (0, R, 0, 10), (0, G, 0, 10), (0, B, 0, 10), (1, R, 0, 4), (0, R, 10, -6), (1, G, 0, 4),
+        (0, G, 10, -6), (1, B, 0, 4), (0, B, 10, -6),
+    ] }),
+];
+
+fn sign_extend(x: u32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((x << shift) as i32) >> shift
+}
+
+fn unquantize_bc6h(x: i32, bits: u32) -> i32 {
+    if bits >= 15 || x == 0 {
+        x
+    } else if x == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((x << 16) + 0x8000) >> bits
+    }
+}
+
+/// Decodes an unsigned BC6H block into half floats
+fn bc6h(block: &[u8]) -> [[u16; 3]; 16] {
+    let mut bits = Bits::new(block);
+    let mode_bits = if block[0] & 2 == 0 {
+        bits.read(2)
+    } else {
+        bits.read(5)
+    };
+    let Some((_, mode)) = BC6H_MODES.iter().find(|(m, _)| *m == mode_bits) else {
+        return [[0; 3]; 16];
+    };
+
+    let mut endpoints = [[0u32; 3]; 4];
+    for &(e, c, bit, count) in mode.fields {
+        let x = bits.read(count.unsigned_abs() as u32);
+        let x = if count < 0 {
+            x.reverse_bits() >> (32 - count.unsigned_abs() as u32)
+        } else {
+            x
+        };
+        endpoints[e as usize][c as usize] |= x << bit;
+    }
+
+    let regions = if mode.two_regions { 2 } else { 1 };
+    let partition_index = if mode.two_regions { bits.read(5) } else { 0 } as usize;
+    let mask = (1u32 << mode.endpoint_bits) - 1;
+    let mut unquantized = [[0i32; 3]; 4];
+    for (e, endpoint) in endpoints.iter().enumerate().take(2 * regions) {
+        for c in 0..3 {
+            let x = if mode.transformed && e > 0 {
+                let delta = sign_extend(endpoint[c], mode.delta_bits[c]);
+                (endpoints[0][c] as i32 + delta) as u32 & mask
+            } else {
+                endpoint[c]
+            };
+            unquantized[e][c] = unquantize_bc6h(x as i32, mode.endpoint_bits);
+        }
+    }
+
+    let (subset, anchor) = partition(regions as u32, partition_index);
+    let index_bits = if mode.two_regions { 3 } else { 4 };
+    let mut out = [[0; 3]; 16];
+    for (i, texel) in out.iter_mut().enumerate() {
+        let index = bits.read(index_bits - anchor[i] as u32);
+        let w = weights(index_bits)[index as usize];
+        let s = subset[i] as usize;
+        for c in 0..3 {
+            let x = interpolate(
+                unquantized[2 * s][c] as u32,
+                unquantized[2 * s + 1][c] as u32,
+                w,
+            );
+            texel[c] = ((x * 31) >> 6) as u16;
+        }
+    }
+    out
+}
+
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (h >> 10 & 0x1F) as i32;
+    let mantissa = (h & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1F if mantissa == 0.0 => f32::INFINITY,
+        0x1F => f32::NAN,
+        e => (1.0 + mantissa / 1024.0) * 2f32.powi(e - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partition_anchors() {
+        for p in 0..64 {
+            let (subset, anchor) = partition(2, p);
+            assert_eq!(subset[ANCHORS2[p] as usize], 1);
+            assert_eq!(anchor.iter().filter(|&&x| x).count(), 2);
+            let (subset, _) = partition(3, p);
+            assert_eq!(subset[ANCHORS3[0][p] as usize], 1);
+            assert_eq!(subset[ANCHORS3[1][p] as usize], 2);
+        }
+    }
+
+    #[test]
+    fn bc1_block() {
+        // red and blue endpoints, alternating indices
+        let block = [0x00, 0xF8, 0x1F, 0x00, 0b11_10_01_00, 0, 0, 0];
+        let rgba = decode_rgba8(TextureFormat::DXT1, 4, 4, &block).unwrap();
+        assert_eq!(
+            &rgba[..16],
+            &[255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255]
+        );
+    }
+
+    #[test]
+    fn bc7_mode6_block() {
+        // mode 6 with both endpoints at 0x7F and all pbits set, decodes to opaque white
+        let mut bits = 1u128 << 6;
+        let mut offset = 7;
+        for _ in 0..8 {
+            bits |= 0x7F << offset;
+            offset += 7;
+        }
+        bits |= 0b11 << offset;
+        let rgba = decode_rgba8(TextureFormat::BC7, 4, 4, &bits.to_le_bytes()).unwrap();
+        assert!(rgba.iter().all(|&x| x == 255));
+    }
+
+    #[test]
+    fn bc6h_mode11_block() {
+        // mode 11 with both endpoints at the maximum of 10 bits, decodes to 65504 in half floats
+        let mut bits = 0b00011u128;
+        for i in 0..6 {
+            bits |= 0x3FF << (5 + 10 * i);
+        }
+        let rgba = decode_rgba32f(4, 4, &bits.to_le_bytes()).unwrap();
+        assert_eq!(rgba[..4], [65504.0, 65504.0, 65504.0, 1.0]);
+    }
+
+    #[test]
+    fn partial_blocks() {
+        let rgba = decode_rgba8(TextureFormat::ATI1, 2, 1, &[255, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(rgba, [255, 255, 255, 255, 255, 255, 255, 255]);
+        assert!(decode_rgba8(TextureFormat::DXT5, 8, 8, &[0; 16]).is_none());
+    }
+
+    #[test]
+    fn decode_into_layouts() {
+        let mip = Mipmap {
+            width: 1,
+            height: 2,
+            format: TextureFormat::RGBA8,
+            data: vec![255, 128, 0, 64, 1, 2, 3, 4].into(),
+            ..Default::default()
+        };
+        assert_eq!(mip.decode_rgba8().unwrap(), [1, 2, 3, 4, 255, 128, 0, 64]);
+        let mut bgra = [0; 12];
+        mip.decode_into(&mut bgra, PixelLayout::Bgra8, Orientation::BottomUp)
+            .unwrap();
+        assert_eq!(bgra, [0, 128, 255, 64, 3, 2, 1, 4, 0, 0, 0, 0]);
+        let mut f32s = [0; 32];
+        mip.decode_into(&mut f32s, PixelLayout::Rgba32F, Orientation::BottomUp)
+            .unwrap();
+        assert_eq!(f32s[..4], 1.0f32.to_ne_bytes());
+        assert_eq!(f32s[8..12], 0.0f32.to_ne_bytes());
+        mip.decode_into(&mut f32s, PixelLayout::Rgba32F, Orientation::TopDown)
+            .unwrap();
+        assert_eq!(f32s[16..20], 1.0f32.to_ne_bytes());
+        assert_eq!(
+            mip.decode_into(&mut bgra[..7], PixelLayout::Bgra8, Orientation::TopDown),
+            Err(Error::BufferTooSmall {
+                expected: 8,
+                available: 7
+            })
+        );
+    }
+
+    #[test]
+    fn decode_game_mipmaps() {
+        let atlas =
+            TextureAtlas::from_bytes(include_bytes!("../assets/mikitm001_tex.txp")).unwrap();
+        for mip in atlas.textures.iter().flat_map(|x| x.mipmaps()) {
+            let rgba = mip.decode_rgba8().unwrap();
+            assert_eq!(rgba.len(), mip.width as usize * mip.height as usize * 4);
+        }
+        let mut mip = atlas.textures[0].subtextures[0].mipmaps[0].clone();
+        mip.data.to_mut().truncate(10);
+        assert!(matches!(
+            mip.decode_rgba8(),
+            Err(Error::TruncatedData { .. })
+        ));
+    }
+}
@@ -0,0 +1,621 @@
+//! Software encoders for every [`TextureFormat`] except BC6H.
+//!
+//! Everything encodes from tightly packed RGBA8. Block compressed formats fit a pair of
+//! endpoints to each 4x4 block, how hard they try is controlled by [`Quality`].
+//! BC7 is always encoded in mode 6.
+use std::convert::TryInto;
+
+use tracing::trace;
+
+use super::decode::{bc1_palette, bc4_palette, interpolate, WEIGHTS4};
+use super::*;
+
+type Vec4 = [f32; 4];
+
+/// Encodes `width`x`height` RGBA8 pixels into `format`
+#[tracing::instrument(level = "trace", skip(rgba))]
+pub(crate) fn encode(
+    format: TextureFormat,
+    width: u32,
+    height: u32,
+    rgba: &[u8],
+    quality: Quality,
+) -> Result<Vec<u8>, Error> {
+    use TextureFormat::*;
+    let pixels = width as usize * height as usize;
+    if rgba.len() < pixels * 4 {
+        return Err(Error::TruncatedData {
+            expected: pixels * 4,
+            available: rgba.len(),
+        });
+    }
+    let px = rgba[..pixels * 4].chunks_exact(4);
+    let data = match format {
+        A8 => px.map(|x| x[3]).collect(),
+        L8 => px.map(luma).collect(),
+        L8A8 => px.flat_map(|x| [luma(x), x[3]]).collect(),
+        RGB8 => px.flat_map(|x| [x[0], x[1], x[2]]).collect(),
+        RGBA8 => rgba[..pixels * 4].to_vec(),
+        RGB5 => px
+            .flat_map(|x| {
+                let x = quantize(x[0], 5) << 11 | quantize(x[1], 6) << 5 | quantize(x[2], 5);
+                x.to_le_bytes()
+            })
+            .collect(),
+        RGB5A1 => px
+            .flat_map(|x| {
+                let a = if x[3] >= 128 { 0x8000 } else { 0 };
+                let x = a | quantize(x[0], 5) << 10 | quantize(x[1], 5) << 5 | quantize(x[2], 5);
+                x.to_le_bytes()
+            })
+            .collect(),
+        RGBA4 => px
+            .flat_map(|x| {
+                let x = quantize(x[3], 4) << 12
+                    | quantize(x[0], 4) << 8
+                    | quantize(x[1], 4) << 4
+                    | quantize(x[2], 4);
+                x.to_le_bytes()
+            })
+            .collect(),
+        DXT1 => encode_blocks(width, height, rgba, |b| {
+            bc1_block(b, quality, Bc1Mode::Opaque)
+        }),
+        DXT1a => encode_blocks(width, height, rgba, |b| {
+            bc1_block(b, quality, Bc1Mode::Punchthrough)
+        }),
+        DXT3 => encode_blocks(width, height, rgba, |b| {
+            let alpha = b.iter().enumerate().fold(0u64, |acc, (i, x)| {
+                acc | (quantize(x[3], 4) as u64) << (4 * i)
+            });
+            concat(alpha.to_le_bytes(), bc1_block(b, quality, Bc1Mode::Color))
+        }),
+        DXT5 => encode_blocks(width, height, rgba, |b| {
+            let alpha = bc4_block(&channel(b, 3), quality);
+            concat(alpha, bc1_block(b, quality, Bc1Mode::Color))
+        }),
+        ATI1 => encode_blocks(width, height, rgba, |b| bc4_block(&channel(b, 0), quality)),
+        ATI2 => encode_blocks(width, height, rgba, |b| {
+            concat(
+                bc4_block(&channel(b, 0), quality),
+                bc4_block(&channel(b, 1), quality),
+            )
+        }),
+        BC7 => encode_blocks(width, height, rgba, |b| bc7_block(b, quality)),
+        BC6H => return Err(Error::UnsupportedFormat(format)),
+    };
+    Ok(data)
+}
+
+/// Rec. 709 luma, the same weights `image` uses
+fn luma(x: &[u8]) -> u8 {
+    let [r, g, b] = [x[0], x[1], x[2]].map(u32::from);
+    ((2126 * r + 7152 * g + 722 * b + 5000) / 10000) as u8
+}
+
+/// Rounds an 8 bit value to `bits`
+fn quantize(x: u8, bits: u32) -> u16 {
+    ((x as u32 * ((1 << bits) - 1) + 127) / 255) as u16
+}
+
+fn concat(a: [u8; 8], b: [u8; 8]) -> [u8; 16] {
+    let mut out = [0; 16];
+    out[..8].copy_from_slice(&a);
+    out[8..].copy_from_slice(&b);
+    out
+}
+
+fn channel(block: &[[u8; 4]; 16], c: usize) -> [u8; 16] {
+    block.map(|x| x[c])
+}
+
+/// Splits the image in 4x4 blocks, partial blocks at the edges repeat the last row or column
+fn encode_blocks<F, const N: usize>(width: u32, height: u32, rgba: &[u8], mut f: F) -> Vec<u8>
+where
+    F: FnMut(&[[u8; 4]; 16]) -> [u8; N],
+{
+    let (width, height) = (width as usize, height as usize);
+    let mut data = Vec::with_capacity(width.div_ceil(4) * height.div_ceil(4) * N);
+    let mut texels = [[0; 4]; 16];
+    for by in (0..height).step_by(4) {
+        for bx in (0..width).step_by(4) {
+            for (j, texel) in texels.iter_mut().enumerate() {
+                let x = (bx + j % 4).min(width - 1);
+                let y = (by + j / 4).min(height - 1);
+                let idx = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[idx..idx + 4]);
+            }
+            data.extend_from_slice(&f(&texels));
+        }
+    }
+    data
+}
+
+fn to_vec4(x: &[u8; 4]) -> Vec4 {
+    x.map(f32::from)
+}
+
+fn distance(a: &[u8; 4], b: &[u8; 4], channels: usize) -> u32 {
+    a.iter()
+        .zip(b)
+        .take(channels)
+        .map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+/// Finds two endpoints spanning the first `channels` channels of `pixels`
+fn fit_endpoints(pixels: &[Vec4], channels: usize, quality: Quality) -> (Vec4, Vec4) {
+    let mut lo = [255.0f32; 4];
+    let mut hi = [0.0f32; 4];
+    let mut mean = [0.0f32; 4];
+    for p in pixels {
+        for c in 0..channels {
+            lo[c] = lo[c].min(p[c]);
+            hi[c] = hi[c].max(p[c]);
+            mean[c] += p[c] / pixels.len() as f32;
+        }
+    }
+    let mut cov = [[0.0f32; 4]; 4];
+    for p in pixels {
+        for i in 0..channels {
+            for j in 0..channels {
+                cov[i][j] += (p[i] - mean[i]) * (p[j] - mean[j]);
+            }
+        }
+    }
+    if quality == Quality::Fast {
+        // the diagonal of the bounding box, flipped for channels going against the widest one
+        let widest = (0..channels)
+            .max_by(|&a, &b| (hi[a] - lo[a]).total_cmp(&(hi[b] - lo[b])))
+            .unwrap_or(0);
+        for c in 0..channels {
+            if cov[widest][c] < 0.0 {
+                std::mem::swap(&mut lo[c], &mut hi[c]);
+            }
+        }
+        return (lo, hi);
+    }
+
+    // power iteration, starting from the diagonal of the bounding box
+    let mut axis = [0.0f32; 4];
+    for c in 0..channels {
+        axis[c] = hi[c] - lo[c];
+    }
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for (i, x) in next.iter_mut().enumerate() {
+            *x = (0..4).map(|j| cov[i][j] * axis[j]).sum();
+        }
+        let norm = next.iter().fold(0.0f32, |acc, x| acc.max(x.abs()));
+        if norm < f32::EPSILON {
+            break;
+        }
+        axis = next.map(|x| x / norm);
+    }
+    let len = axis.iter().map(|x| x * x).sum::<f32>();
+    if len < f32::EPSILON {
+        return (lo, hi);
+    }
+
+    let (mut tmin, mut tmax) = (f32::MAX, f32::MIN);
+    for p in pixels {
+        let t = (0..channels)
+            .map(|c| (p[c] - mean[c]) * axis[c])
+            .sum::<f32>()
+            / len;
+        tmin = tmin.min(t);
+        tmax = tmax.max(t);
+    }
+    let at = |t: f32| {
+        let mut e = mean;
+        for c in 0..channels {
+            e[c] = (mean[c] + axis[c] * t).clamp(0.0, 255.0);
+        }
+        e
+    };
+    (at(tmin), at(tmax))
+}
+
+/// Least squares endpoints for `pixels`, given the interpolation weight of each pixel
+fn refine_endpoints(pixels: &[Vec4], weights: &[f32]) -> Option<(Vec4, Vec4)> {
+    let (mut aa, mut ab, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+    let mut ax = [0.0f32; 4];
+    let mut bx = [0.0f32; 4];
+    for (p, &w) in pixels.iter().zip(weights) {
+        let a = 1.0 - w;
+        aa += a * a;
+        ab += a * w;
+        bb += w * w;
+        for c in 0..4 {
+            ax[c] += a * p[c];
+            bx[c] += w * p[c];
+        }
+    }
+    let det = aa * bb - ab * ab;
+    if det.abs() < 1e-6 {
+        return None;
+    }
+    let mut e0 = [0.0f32; 4];
+    let mut e1 = [0.0f32; 4];
+    for c in 0..4 {
+        e0[c] = ((bb * ax[c] - ab * bx[c]) / det).clamp(0.0, 255.0);
+        e1[c] = ((aa * bx[c] - ab * ax[c]) / det).clamp(0.0, 255.0);
+    }
+    Some((e0, e1))
+}
+
+/// How the decoder interprets the endpoints of a BC1 block
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Bc1Mode {
+    /// DXT1, `c0 <= c1` selects three colors and black, which GPUs decode as transparent black
+    /// so it is never used
+    Opaque,
+    /// DXT1a, `c0 <= c1` selects three colors and transparent
+    Punchthrough,
+    /// The color half of DXT3 and DXT5, always four colors
+    Color,
+}
+
+fn to_rgb565(e: Vec4) -> u16 {
+    let q = |x: f32, max: f32| (x / 255.0 * max).round().clamp(0.0, max) as u16;
+    q(e[0], 31.0) << 11 | q(e[1], 63.0) << 5 | q(e[2], 31.0)
+}
+
+fn bc1_block(block: &[[u8; 4]; 16], quality: Quality, mode: Bc1Mode) -> [u8; 8] {
+    let transparent = block.map(|x| mode == Bc1Mode::Punchthrough && x[3] < 128);
+    let opaque: Vec<Vec4> = block
+        .iter()
+        .zip(transparent)
+        .filter(|(_, t)| !t)
+        .map(|(x, _)| to_vec4(x))
+        .collect();
+    if opaque.is_empty() {
+        return [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    }
+
+    let (e0, e1) = fit_endpoints(&opaque, 3, quality);
+    let mut best = bc1_encode(block, &transparent, e0, e1, mode);
+    if quality == Quality::Best {
+        for _ in 0..2 {
+            let indices = u32::from_le_bytes(best.0[4..].try_into().unwrap());
+            let c0 = u16::from_le_bytes([best.0[0], best.0[1]]);
+            let c1 = u16::from_le_bytes([best.0[2], best.0[3]]);
+            let four_colors = mode == Bc1Mode::Color || c0 > c1;
+            let weights = if four_colors {
+                [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0]
+            } else {
+                [0.0, 1.0, 0.5, 0.0]
+            };
+            // in three color mode, the last index is black or transparent and not interpolated
+            let (pixels, weights): (Vec<_>, Vec<_>) = (0..16)
+                .map(|i| (i, (indices >> (2 * i) & 3) as usize))
+                .filter(|&(_, index)| four_colors || index != 3)
+                .map(|(i, index)| (to_vec4(&block[i]), weights[index]))
+                .unzip();
+            let Some((e0, e1)) = refine_endpoints(&pixels, &weights) else {
+                break;
+            };
+            let candidate = bc1_encode(block, &transparent, e0, e1, mode);
+            if candidate.1 >= best.1 {
+                break;
+            }
+            best = candidate;
+        }
+    }
+    best.0
+}
+
+/// Quantizes the endpoints, orders them for `mode` and picks the closest color for each pixel
+fn bc1_encode(
+    block: &[[u8; 4]; 16],
+    transparent: &[bool; 16],
+    e0: Vec4,
+    e1: Vec4,
+    mode: Bc1Mode,
+) -> ([u8; 8], u32) {
+    let (mut c0, mut c1) = (to_rgb565(e0), to_rgb565(e1));
+    let three_colors = transparent.iter().any(|&t| t);
+    if (three_colors && c0 > c1) || (!three_colors && mode != Bc1Mode::Color && c0 < c1) {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let four_colors = mode == Bc1Mode::Color || c0 > c1;
+    let candidates = if four_colors { 4 } else { 3 };
+
+    let mut out = [0; 8];
+    out[..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    let palette = bc1_palette(&out, four_colors, mode == Bc1Mode::Punchthrough);
+    let mut indices = 0u32;
+    let mut error = 0;
+    for (i, px) in block.iter().enumerate() {
+        let index = if transparent[i] {
+            3
+        } else {
+            let (index, d) = (0..candidates)
+                .map(|k| (k, distance(&palette[k], px, 3)))
+                .min_by_key(|&(_, d)| d)
+                .unwrap();
+            error += d;
+            index
+        };
+        indices |= (index as u32) << (2 * i);
+    }
+    out[4..].copy_from_slice(&indices.to_le_bytes());
+    (out, error)
+}
+
+fn bc4_block(values: &[u8; 16], quality: Quality) -> [u8; 8] {
+    let lo = *values.iter().min().unwrap();
+    let hi = *values.iter().max().unwrap();
+    let mut best = bc4_encode(values, hi, lo);
+    if quality == Quality::Fast {
+        return best.0;
+    }
+
+    // six interpolated values, with 0 and 255 available for the extremes
+    let inner = values.iter().filter(|&&x| x != 0 && x != 255);
+    if let (Some(&lo), Some(&hi)) = (inner.clone().min(), inner.max()) {
+        let candidate = bc4_encode(values, lo, hi);
+        if candidate.1 < best.1 {
+            best = candidate;
+        }
+    }
+    if quality == Quality::Best && hi > lo {
+        let range = (hi - lo) / 8;
+        for inset_lo in 0..=range {
+            for inset_hi in 0..=range {
+                let candidate = bc4_encode(values, hi - inset_hi, lo + inset_lo);
+                if candidate.1 < best.1 {
+                    best = candidate;
+                }
+            }
+        }
+    }
+    trace!(error = best.1);
+    best.0
+}
+
+fn bc4_encode(values: &[u8; 16], a0: u8, a1: u8) -> ([u8; 8], u32) {
+    let palette = bc4_palette(a0, a1);
+    let mut indices = 0u64;
+    let mut error = 0;
+    for (i, &x) in values.iter().enumerate() {
+        let (index, d) = palette
+            .iter()
+            .map(|&p| (p as i32 - x as i32).pow(2) as u32)
+            .enumerate()
+            .min_by_key(|&(_, d)| d)
+            .unwrap();
+        error += d;
+        indices |= (index as u64) << (3 * i);
+    }
+    let mut out = [0; 8];
+    out[0] = a0;
+    out[1] = a1;
+    out[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    (out, error)
+}
+
+/// Writes bits into a 128-bit block, least significant bit first
+struct BitWriter {
+    bits: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, x: u32, n: u32) {
+        self.bits |= ((x & ((1 << n) - 1)) as u128) << self.offset;
+        self.offset += n;
+    }
+}
+
+fn bc7_block(block: &[[u8; 4]; 16], quality: Quality) -> [u8; 16] {
+    let pixels = block.map(|x| to_vec4(&x));
+    let (e0, e1) = fit_endpoints(&pixels, 4, quality);
+    let mut best = bc7_encode(block, e0, e1, quality);
+    if quality == Quality::Best {
+        for _ in 0..2 {
+            let weights = best.2.map(|i| WEIGHTS4[i as usize] as f32 / 64.0);
+            let Some((e0, e1)) = refine_endpoints(&pixels, &weights) else {
+                break;
+            };
+            let candidate = bc7_encode(block, e0, e1, quality);
+            if candidate.1 >= best.1 {
+                break;
+            }
+            best = candidate;
+        }
+    }
+    best.0
+}
+
+/// Quantizes a mode 6 endpoint to 7 bits per channel and a shared `pbit`, returns the 7 bit values
+fn bc7_quantize(e: Vec4, pbit: u32) -> [u32; 4] {
+    e.map(|x| ((x - pbit as f32) / 2.0).round().clamp(0.0, 127.0) as u32)
+}
+
+fn bc7_expand(q: [u32; 4], pbit: u32) -> [u32; 4] {
+    q.map(|x| x << 1 | pbit)
+}
+
+fn bc7_quantization_error(e: Vec4, pbit: u32) -> f32 {
+    let expanded = bc7_expand(bc7_quantize(e, pbit), pbit);
+    e.iter()
+        .zip(expanded)
+        .map(|(&x, q)| (x - q as f32).powi(2))
+        .sum()
+}
+
+/// Encodes a mode 6 block, returns the block, its error and the index of each pixel
+fn bc7_encode(
+    block: &[[u8; 4]; 16],
+    e0: Vec4,
+    e1: Vec4,
+    quality: Quality,
+) -> ([u8; 16], u32, [u32; 16]) {
+    let pick = |e: Vec4| (bc7_quantization_error(e, 1) < bc7_quantization_error(e, 0)) as u32;
+    let pbits: &[(u32, u32)] = if quality == Quality::Best {
+        &[(0, 0), (0, 1), (1, 0), (1, 1)]
+    } else {
+        &[(pick(e0), pick(e1))]
+    };
+
+    let candidate = |&(p0, p1): &(u32, u32)| {
+        let q0 = bc7_quantize(e0, p0);
+        let q1 = bc7_quantize(e1, p1);
+        let (x0, x1) = (bc7_expand(q0, p0), bc7_expand(q1, p1));
+        let palette: Vec<[u8; 4]> = WEIGHTS4
+            .iter()
+            .map(|&w| [0, 1, 2, 3].map(|c| interpolate(x0[c], x1[c], w) as u8))
+            .collect();
+        let mut indices = [0; 16];
+        let mut error = 0;
+        for (px, index) in block.iter().zip(indices.iter_mut()) {
+            let (i, d) = palette
+                .iter()
+                .map(|p| distance(p, px, 4))
+                .enumerate()
+                .min_by_key(|&(_, d)| d)
+                .unwrap();
+            *index = i as u32;
+            error += d;
+        }
+        (error, q0, q1, p0, p1, indices)
+    };
+    let (error, mut q0, mut q1, mut p0, mut p1, mut indices) =
+        pbits.iter().map(candidate).min_by_key(|x| x.0).unwrap();
+
+    // the most significant bit of the anchor index is implicitly zero
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut w = BitWriter {
+        bits: 1 << 6,
+        offset: 7,
+    };
+    for c in 0..4 {
+        w.write(q0[c], 7);
+        w.write(q1[c], 7);
+    }
+    w.write(p0, 1);
+    w.write(p1, 1);
+    for (i, &index) in indices.iter().enumerate() {
+        w.write(index, if i == 0 { 3 } else { 4 });
+    }
+    (w.bits.to_le_bytes(), error, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::decode_rgba8;
+
+    /// A smooth ramp with some noise, every channel follows the ramp
+    fn gradient(width: u32, height: u32) -> Vec<u8> {
+        let mut rgba = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                let t = (x + y * width) * 255 / (width * height);
+                let noise = (x * 7 + y * 13) % 5;
+                rgba.extend([t, 255 - t, t / 2 + noise * 4, t].map(|x| x as u8));
+            }
+        }
+        rgba
+    }
+
+    fn error(format: TextureFormat, quality: Quality, rgba: &[u8], channels: usize) -> f64 {
+        let data = encode(format, 16, 16, rgba, quality).unwrap();
+        let decoded = decode_rgba8(format, 16, 16, &data).unwrap();
+        let sum: u64 = rgba
+            .chunks_exact(4)
+            .zip(decoded.chunks_exact(4))
+            .map(|(a, b)| distance(a.try_into().unwrap(), b.try_into().unwrap(), channels) as u64)
+            .sum();
+        sum as f64 / 256.0
+    }
+
+    #[test]
+    fn roundtrip_blocks() {
+        use TextureFormat::*;
+        let rgba = gradient(16, 16);
+        for (format, channels, max) in [
+            (DXT1, 3, 100.0),
+            (DXT3, 4, 120.0),
+            (DXT5, 4, 100.0),
+            (BC7, 4, 60.0),
+        ] {
+            let mut last = f64::MAX;
+            for quality in [Quality::Fast, Quality::Normal, Quality::Best] {
+                let error = error(format, quality, &rgba, channels);
+                assert!(error < max, "{:?} {:?} {}", format, quality, error);
+                assert!(error <= last * 1.05, "{:?} {:?} {}", format, quality, error);
+                last = error;
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_channels() {
+        let rgba = gradient(16, 16);
+        let data = encode(TextureFormat::ATI2, 16, 16, &rgba, Quality::Normal).unwrap();
+        let decoded = decode_rgba8(TextureFormat::ATI2, 16, 16, &data).unwrap();
+        for (a, b) in rgba.chunks_exact(4).zip(decoded.chunks_exact(4)) {
+            assert!((a[0] as i32 - b[0] as i32).abs() <= 4);
+            assert!((a[1] as i32 - b[1] as i32).abs() <= 4);
+        }
+    }
+
+    #[test]
+    fn punchthrough() {
+        let mut rgba = gradient(4, 4);
+        for (i, px) in rgba.chunks_exact_mut(4).enumerate() {
+            px[3] = if i % 3 == 0 { 0 } else { 255 };
+        }
+        let data = encode(TextureFormat::DXT1a, 4, 4, &rgba, Quality::Best).unwrap();
+        let decoded = decode_rgba8(TextureFormat::DXT1a, 4, 4, &data).unwrap();
+        for (a, b) in rgba.chunks_exact(4).zip(decoded.chunks_exact(4)) {
+            assert_eq!(a[3], b[3]);
+        }
+    }
+
+    #[test]
+    fn opaque_dxt1() {
+        let mut rgba = gradient(16, 16);
+        // solid blocks have equal endpoints, which select three colors
+        rgba[..16 * 4 * 4].fill(0x80);
+        for quality in [Quality::Fast, Quality::Normal, Quality::Best] {
+            let data = encode(TextureFormat::DXT1, 16, 16, &rgba, quality).unwrap();
+            let decoded = decode_rgba8(TextureFormat::DXT1a, 16, 16, &data).unwrap();
+            assert!(
+                decoded.chunks_exact(4).all(|x| x[3] == 255),
+                "{:?}",
+                quality
+            );
+        }
+        // black pixels in a block whose endpoints ended up equal
+        let mut block = [[128, 128, 128, 255]; 16];
+        block[..8].fill([0, 0, 0, 255]);
+        let e = [128.0; 4];
+        let (data, _) = bc1_encode(&block, &[false; 16], e, e, Bc1Mode::Opaque);
+        let decoded = decode_rgba8(TextureFormat::DXT1a, 4, 4, &data).unwrap();
+        assert!(decoded.chunks_exact(4).all(|x| x[3] == 255));
+    }
+
+    #[test]
+    fn uncompressed() {
+        use TextureFormat::*;
+        let rgba = gradient(5, 3);
+        for format in [A8, L8, L8A8, RGB8, RGBA8, RGB5, RGB5A1, RGBA4] {
+            let data = encode(format, 5, 3, &rgba, Quality::Fast).unwrap();
+            assert_eq!(data.len(), format.data_len(5, 3));
+        }
+        assert_eq!(encode(RGBA8, 5, 3, &rgba, Quality::Fast).unwrap(), rgba);
+        assert_eq!(
+            encode(BC6H, 5, 3, &rgba, Quality::Fast),
+            Err(Error::UnsupportedFormat(BC6H))
+        );
+    }
+}
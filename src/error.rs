@@ -0,0 +1,110 @@
+use std::fmt;
+
+use nom::error::{ErrorKind, ParseError};
+
+use crate::TextureFormat;
+
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Error {
+    /// The signature did not start with `TXP` or had the wrong section or archive id
+    BadMagic { found: [u8; 4] },
+    /// An offset table pointed past the end of the file
+    OffsetOutOfBounds { offset: usize, len: usize },
+    /// The mipmap format id is not a known [`TextureFormat`]
+    UnknownFormat(u32),
+    /// The input ended before the expected amount of data
+    TruncatedData { expected: usize, available: usize },
+    /// A count does not fit in the remaining input
+    CountOverflow(u32),
+    /// An array texture whose mipmap count is not a multiple of its depth
+    InvalidDepth { mip_count: u32, depth: u32 },
+    /// The format has no encoder
+    UnsupportedFormat(TextureFormat),
+    /// The input of a conversion uses something TXP cannot represent, like a format or volume textures
+    Unrepresentable(String),
+    /// A sprite set has no sprite with this name
+    UnknownSprite(String),
+    /// A sprite points to a texture that is not in the atlas
+    MissingTexture(u32),
+    /// The texture data cannot be decoded into an image
+    Undecodable(String),
+    /// Compressed data could not be inflated
+    Decompress(String),
+    /// An image could not be encoded or written by the `image` crate
+    Image(String),
+    /// The data cannot be flipped without decoding it, like BC7 blocks or rows split across blocks
+    Unflippable(String),
+    /// An output buffer cannot hold the decoded pixels
+    BufferTooSmall { expected: usize, available: usize },
+    /// Any other parsing failure
+    Parse(ErrorKind),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic { found } => write!(f, "bad magic {:02X?}", found),
+            Self::OffsetOutOfBounds { offset, len } => write!(
+                f,
+                "offset {:#X} is out of bounds of a file of length {:#X}",
+                offset, len
+            ),
+            Self::UnknownFormat(id) => write!(f, "unknown texture format id {}", id),
+            Self::TruncatedData {
+                expected,
+                available,
+            } => write!(
+                f,
+                "truncated data: expected {} bytes but only {} are available",
+                expected, available
+            ),
+            Self::CountOverflow(count) => {
+                write!(f, "count {} does not fit in the remaining input", count)
+            }
+            Self::InvalidDepth { mip_count, depth } => write!(
+                f,
+                "{} mipmaps cannot be split into an array of depth {}",
+                mip_count, depth
+            ),
+            Self::UnsupportedFormat(format) => write!(f, "cannot encode to {:?}", format),
+            Self::Unrepresentable(what) => write!(f, "txp cannot represent {}", what),
+            Self::UnknownSprite(name) => write!(f, "no sprite named `{}`", name),
+            Self::MissingTexture(index) => write!(f, "texture #{} is not in the atlas", index),
+            Self::Undecodable(what) => write!(f, "cannot decode {}", what),
+            Self::Decompress(e) => write!(f, "failed to decompress: {}", e),
+            Self::Image(e) => write!(f, "failed to save the image: {}", e),
+            Self::Unflippable(what) => write!(f, "cannot flip {} without decoding it", what),
+            Self::BufferTooSmall {
+                expected,
+                available,
+            } => write!(
+                f,
+                "buffer too small: {} bytes are needed but only {} are available",
+                expected, available
+            ),
+            Self::Parse(kind) => write!(f, "failed to parse: {}", kind.description()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl<I> ParseError<I> for Error {
+    fn from_error_kind(_: I, kind: ErrorKind) -> Self {
+        Self::Parse(kind)
+    }
+
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl From<nom::Err<Error>> for Error {
+    fn from(err: nom::Err<Error>) -> Self {
+        match err {
+            nom::Err::Error(e) | nom::Err::Failure(e) => e,
+            nom::Err::Incomplete(_) => Self::Parse(ErrorKind::Complete),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! FARC archives, the containers the games ship their files in.
+//!
+//! Every archive starts with a big endian header: the signature, the size of the header after
+//! that field, and a list of entries, each a NUL-terminated name followed by the offset and the
+//! size of its data. `FArC` archives compress every entry, `FARC` archives compress the entries
+//! whose stored size differs from their size.
+//!
+//! Encrypted `FARC` archives are not supported.
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nom::bytes::complete::take_till;
+use nom::number::complete::be_u32;
+use tracing::{debug, trace};
+
+use super::read::{take_exact, IResult};
+use super::*;
+
+const FLAG_COMPRESSED: u32 = 2;
+const FLAG_ENCRYPTED: u32 = 4;
+const DEFAULT_ALIGNMENT: u32 = 0x10;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Farc<'a> {
+    pub kind: FarcKind,
+    /// Alignment of the data of every entry
+    pub alignment: u32,
+    pub entries: Vec<FarcEntry<'a>>,
+}
+
+/// The signature of an archive, and how it stores its entries
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum FarcKind {
+    /// `FArc`, entries are stored as-is
+    #[default]
+    Plain,
+    /// `FArC`, every entry is compressed
+    Compressed,
+    /// `FARC`, entries are compressed if the archive is flagged as compressed
+    Extended { compressed: bool },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FarcEntry<'a> {
+    pub name: String,
+    /// The decompressed data
+    pub data: Cow<'a, [u8]>,
+}
+
+impl<'a> Farc<'a> {
+    /// An empty archive aligning its data to `0x10` bytes, like the games do
+    pub fn new(kind: FarcKind) -> Self {
+        Self {
+            kind,
+            alignment: DEFAULT_ALIGNMENT,
+            entries: vec![],
+        }
+    }
+
+    /// Replaces the data of the entry named `name`, or adds it at the end of the archive
+    pub fn insert(&mut self, name: impl Into<String>, data: impl Into<Cow<'a, [u8]>>) {
+        let name = name.into();
+        let data = data.into();
+        match self.entries.iter_mut().find(|x| x.name == name) {
+            Some(entry) => entry.data = data,
+            None => self.entries.push(FarcEntry { name, data }),
+        }
+    }
+
+    /// Parses an archive and decompresses its entries, reporting why the file could not be read
+    pub fn from_bytes(input: &'a [u8]) -> Result<Self, Error> {
+        let (_, farc) = Self::parse(input)?;
+        Ok(farc)
+    }
+
+    #[tracing::instrument(name = "farc", skip(i0))]
+    pub fn parse(i0: &'a [u8]) -> IResult<&'a [u8], Farc<'a>> {
+        let (i, signature) = take_exact(4)(i0)?;
+        if !matches!(signature, b"FArc" | b"FArC" | b"FARC") {
+            return Err(nom::Err::Error(Error::BadMagic {
+                found: signature.try_into().unwrap(),
+            }));
+        }
+        let (i, header_size) = be_u32(i)?;
+        let header_end = 8 + header_size as usize;
+        let (i, kind, alignment) = match signature {
+            b"FArc" => {
+                let (i, alignment) = be_u32(i)?;
+                (i, FarcKind::Plain, alignment)
+            }
+            b"FArC" => {
+                let (i, alignment) = be_u32(i)?;
+                (i, FarcKind::Compressed, alignment)
+            }
+            _ => {
+                let (i, flags) = be_u32(i)?;
+                let (i, _padding) = be_u32(i)?;
+                let (i, alignment) = be_u32(i)?;
+                if flags & FLAG_ENCRYPTED != 0 {
+                    return Err(nom::Err::Failure(Error::Unrepresentable(
+                        "encrypted archives".into(),
+                    )));
+                }
+                let compressed = flags & FLAG_COMPRESSED != 0;
+                (i, FarcKind::Extended { compressed }, alignment)
+            }
+        };
+        debug!(?kind, header_size, alignment);
+
+        let start = i0.len() - i.len();
+        let table = i0
+            .get(start..header_end.max(start))
+            .ok_or(nom::Err::Failure(Error::TruncatedData {
+                expected: header_end,
+                available: i0.len(),
+            }))?;
+        let mut i = table;
+        let mut entries = vec![];
+        // the header may be padded with zeroes after the last entry
+        while i.iter().any(|&x| x != 0) {
+            let (rest, entry) = FarcEntry::parse(i0, i, kind)?;
+            entries.push(entry);
+            i = rest;
+        }
+        let rest = &i0[header_end.max(start)..];
+        Ok((
+            rest,
+            Self {
+                kind,
+                alignment,
+                entries,
+            },
+        ))
+    }
+
+    /// The decompressed data of the entry named `name`
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| &x.data[..])
+    }
+}
+
+impl<'a> FarcEntry<'a> {
+    fn parse(i0: &'a [u8], i: &'a [u8], kind: FarcKind) -> IResult<&'a [u8], FarcEntry<'a>> {
+        let (i, name) = take_till(|x| x == 0)(i)?;
+        let (i, _) = take_exact(1)(i)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        let (i, offset) = be_u32(i)?;
+        let (i, stored_size) = be_u32(i)?;
+        let (i, size) = match kind {
+            FarcKind::Plain => (i, stored_size),
+            _ => be_u32(i)?,
+        };
+        trace!(%name, offset, stored_size, size);
+
+        let (offset, stored_size) = (offset as usize, stored_size as usize);
+        let stored = i0
+            .get(offset..)
+            .ok_or(nom::Err::Failure(Error::OffsetOutOfBounds {
+                offset,
+                len: i0.len(),
+            }))?;
+        let (_, stored) = take_exact(stored_size)(stored)?;
+        let compressed = match kind {
+            FarcKind::Plain => false,
+            FarcKind::Compressed => true,
+            FarcKind::Extended { compressed } => compressed && stored_size != size as usize,
+        };
+        let data = if compressed {
+            Cow::Owned(decompress(stored, size as usize).map_err(nom::Err::Failure)?)
+        } else {
+            Cow::Borrowed(stored)
+        };
+        Ok((i, Self { name, data }))
+    }
+}
+
+impl Farc<'_> {
+    fn header_len(&self) -> usize {
+        let fields = match self.kind {
+            FarcKind::Extended { .. } => 20,
+            _ => 12,
+        };
+        let sizes = match self.kind {
+            FarcKind::Plain => 8,
+            _ => 12,
+        };
+        let entries: usize = self.entries.iter().map(|x| x.name.len() + 1 + sizes).sum();
+        fields + entries
+    }
+
+    /// The data of every entry as it is stored, compressed if the archive calls for it
+    fn stored(&self) -> io::Result<Vec<Cow<'_, [u8]>>> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let compress = match self.kind {
+                    FarcKind::Plain => false,
+                    FarcKind::Compressed => true,
+                    FarcKind::Extended { compressed } => compressed,
+                };
+                if !compress {
+                    return Ok(Cow::Borrowed(&entry.data[..]));
+                }
+                let mut enc = GzEncoder::new(vec![], Compression::default());
+                enc.write_all(&entry.data)?;
+                let gzip = enc.finish()?;
+                // `FARC` entries are only read as compressed if their stored size differs
+                if matches!(self.kind, FarcKind::Extended { .. }) && gzip.len() >= entry.data.len()
+                {
+                    return Ok(Cow::Borrowed(&entry.data[..]));
+                }
+                Ok(Cow::Owned(gzip))
+            })
+            .collect()
+    }
+
+    /// Writes the archive, compressing its entries with gzip if its kind calls for it.
+    ///
+    /// The data of every entry starts on a multiple of the alignment, padded with zeroes
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let stored = self.stored()?;
+        let align = (self.alignment as usize).max(1);
+        let header_len = self.header_len();
+        debug!(kind = ?self.kind, entries = self.entries.len(), header_len);
+
+        let signature = match self.kind {
+            FarcKind::Plain => b"FArc",
+            FarcKind::Compressed => b"FArC",
+            FarcKind::Extended { .. } => b"FARC",
+        };
+        w.write_all(signature)?;
+        w.write_all(&(header_len as u32 - 8).to_be_bytes())?;
+        if let FarcKind::Extended { compressed } = self.kind {
+            let flags = if compressed { FLAG_COMPRESSED } else { 0 };
+            w.write_all(&flags.to_be_bytes())?;
+            w.write_all(&0u32.to_be_bytes())?;
+        }
+        w.write_all(&self.alignment.to_be_bytes())?;
+
+        let mut offset = header_len.next_multiple_of(align);
+        for (entry, stored) in self.entries.iter().zip(&stored) {
+            w.write_all(entry.name.as_bytes())?;
+            w.write_all(&[0])?;
+            w.write_all(&(offset as u32).to_be_bytes())?;
+            w.write_all(&(stored.len() as u32).to_be_bytes())?;
+            if self.kind != FarcKind::Plain {
+                w.write_all(&(entry.data.len() as u32).to_be_bytes())?;
+            }
+            offset = (offset + stored.len()).next_multiple_of(align);
+        }
+
+        let mut pos = header_len;
+        for stored in &stored {
+            w.write_all(&vec![0; pos.next_multiple_of(align) - pos])?;
+            pos = pos.next_multiple_of(align);
+            w.write_all(stored)?;
+            pos += stored.len();
+        }
+        w.write_all(&vec![0; pos.next_multiple_of(align) - pos])
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut vec = vec![];
+        self.write(&mut vec).expect("writing to a Vec cannot fail");
+        vec
+    }
+}
+
+/// Inflates gzip data, or zlib data as some tools write it instead
+///
+/// `size` comes from the entry header, so it only bounds the output and is not trusted for the
+/// initial allocation
+fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(4)));
+    // one byte more than expected is enough to tell that the entry is too long
+    let limit = size as u64 + 1;
+    let res = if data.starts_with(&[0x1F, 0x8B]) {
+        GzDecoder::new(data).take(limit).read_to_end(&mut out)
+    } else {
+        ZlibDecoder::new(data).take(limit).read_to_end(&mut out)
+    };
+    res.map_err(|e| Error::Decompress(e.to_string()))?;
+    if out.len() != size {
+        return Err(Error::TruncatedData {
+            expected: size,
+            available: out.len(),
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use std::io::Write;
+
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    /// Builds an archive of `SPR` by hand, `stored` is the data of the entry as it is stored
+    fn archive(signature: &[u8; 4], flags: Option<u32>, stored: &[u8], sizes: bool) -> Vec<u8> {
+        let name = b"spr_sel_pv616.bin\0";
+        let mut header = vec![];
+        match flags {
+            Some(flags) => header.extend([flags, 0, 0x10].iter().flat_map(|x| x.to_be_bytes())),
+            None => header.extend(0x10u32.to_be_bytes()),
+        }
+        let entry_len = name.len() + if sizes { 12 } else { 8 };
+        let offset = (8 + header.len() + entry_len).next_multiple_of(0x10);
+        header.extend(name);
+        header.extend((offset as u32).to_be_bytes());
+        header.extend((stored.len() as u32).to_be_bytes());
+        if sizes {
+            header.extend((SPR.len() as u32).to_be_bytes());
+        }
+        header.resize(offset - 8, 0);
+
+        let mut out = signature.to_vec();
+        out.extend((header.len() as u32).to_be_bytes());
+        out.extend(header);
+        out.extend(stored);
+        out
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = GzEncoder::new(vec![], Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    fn check(bytes: &[u8], kind: FarcKind) {
+        let farc = Farc::from_bytes(bytes).unwrap();
+        assert_eq!(farc.kind, kind);
+        assert_eq!(farc.alignment, 0x10);
+        assert_eq!(farc.entries.len(), 1);
+        let data = farc.get("spr_sel_pv616.bin").unwrap();
+        assert!(data == SPR);
+        TextureAtlas::from_bytes(data).unwrap();
+    }
+
+    #[test]
+    fn read_archives() {
+        check(&archive(b"FArc", None, SPR, false), FarcKind::Plain);
+        check(
+            &archive(b"FArC", None, &gzip(SPR), true),
+            FarcKind::Compressed,
+        );
+        let extended = |compressed| FarcKind::Extended { compressed };
+        check(&archive(b"FARC", Some(0), SPR, true), extended(false));
+        check(
+            &archive(b"FARC", Some(FLAG_COMPRESSED), &gzip(SPR), true),
+            extended(true),
+        );
+
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(SPR).unwrap();
+        let zlib = zlib.finish().unwrap();
+        check(&archive(b"FArC", None, &zlib, true), FarcKind::Compressed);
+    }
+
+    #[test]
+    fn roundtrip_archives() {
+        let kinds = [
+            FarcKind::Plain,
+            FarcKind::Compressed,
+            FarcKind::Extended { compressed: false },
+            FarcKind::Extended { compressed: true },
+        ];
+        for kind in kinds {
+            let mut farc = Farc::new(kind);
+            farc.insert("spr_sel_pv616.bin", SPR);
+            farc.insert("odd.txt", &b"abc"[..]);
+            let bytes = farc.to_bytes();
+            assert_eq!(bytes.len() % 0x10, 0, "{:?}", kind);
+            let read = Farc::from_bytes(&bytes).unwrap();
+            assert_eq!(read, farc, "{:?}", kind);
+            for entry in &read.entries {
+                // entries stored as-is borrow the archive, their offset can be checked
+                if let Cow::Borrowed(data) = entry.data {
+                    let offset = data.as_ptr() as usize - bytes.as_ptr() as usize;
+                    assert_eq!(offset % 0x10, 0, "{:?} {}", kind, entry.name);
+                }
+            }
+        }
+        let compressed = Farc {
+            kind: FarcKind::Compressed,
+            ..Farc::new(FarcKind::Plain)
+        };
+        assert!(compressed.to_bytes().starts_with(b"FArC"));
+    }
+
+    #[test]
+    fn replace_entry() {
+        let mut farc = Farc::new(FarcKind::Compressed);
+        farc.insert("a", &b"old"[..]);
+        farc.insert("b", &b"kept"[..]);
+        farc.insert("a", b"new".to_vec());
+        assert_eq!(farc.entries.len(), 2);
+        assert_eq!(farc.get("a"), Some(&b"new"[..]));
+    }
+
+    #[test]
+    fn errors() {
+        let encrypted = archive(b"FARC", Some(FLAG_ENCRYPTED), SPR, true);
+        assert!(matches!(
+            Farc::from_bytes(&encrypted),
+            Err(Error::Unrepresentable(_))
+        ));
+        let mut truncated = archive(b"FArc", None, SPR, false);
+        truncated.pop();
+        assert!(matches!(
+            Farc::from_bytes(&truncated),
+            Err(Error::TruncatedData { .. })
+        ));
+        // the size of a compressed entry is not trusted for allocating
+        let mut oversized = archive(b"FArC", None, &gzip(SPR), true);
+        oversized[38..42].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            Farc::from_bytes(&oversized),
+            Err(Error::TruncatedData {
+                expected: u32::MAX as usize,
+                available: SPR.len()
+            })
+        );
+        let mut undersized = archive(b"FArC", None, &gzip(SPR), true);
+        undersized[38..42].copy_from_slice(&(SPR.len() as u32 - 10).to_be_bytes());
+        assert_eq!(
+            Farc::from_bytes(&undersized),
+            Err(Error::TruncatedData {
+                expected: SPR.len() - 10,
+                available: SPR.len() - 9
+            })
+        );
+        assert!(matches!(Farc::from_bytes(SPR), Err(Error::BadMagic { .. })));
+        assert!(matches!(
+            Farc::from_bytes(&SPR[..4]),
+            Err(Error::BadMagic { .. })
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+//! Flipping textures upside down without decoding them, to convert between [`Orientation`]s.
+//!
+//! Block compressed formats are flipped a row of blocks at a time, then the rows of pixels inside
+//! every block are reversed. The rows of pixels have to line up with the rows of blocks, so the
+//! height has to be a multiple of 4 or fit in a single block. BC6H and BC7 cannot be flipped, the
+//! shapes of their partitions are not symmetric.
+use tracing::debug;
+
+use super::*;
+
+impl Texture<'_> {
+    /// Whether every mipmap can be flipped without decoding it
+    pub fn is_flippable(&self) -> bool {
+        self.mipmaps().all(Mipmap::is_flippable)
+    }
+
+    /// Flips every mipmap upside down, see [`Mipmap::flip_vertical`]
+    pub fn flip_vertical(&mut self) -> Result<(), Error> {
+        self.subtextures
+            .iter_mut()
+            .try_for_each(Subtexture::flip_vertical)
+    }
+
+    /// Flips the texture between the way it is stored and `orientation`, `None` flips it top-down
+    /// when it can be flipped and keeps it bottom-up otherwise
+    pub(crate) fn flip_to(&mut self, orientation: Option<Orientation>) -> Result<(), Error> {
+        match orientation {
+            Some(Orientation::BottomUp) => Ok(()),
+            Some(Orientation::TopDown) => self.flip_vertical(),
+            None if self.is_flippable() => self.flip_vertical(),
+            None => {
+                debug!("the texture cannot be flipped, it is kept bottom-up");
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Subtexture<'_> {
+    /// Flips every mipmap upside down, see [`Mipmap::flip_vertical`]
+    pub fn flip_vertical(&mut self) -> Result<(), Error> {
+        self.mipmaps.iter_mut().try_for_each(Mipmap::flip_vertical)
+    }
+}
+
+impl Mipmap<'_> {
+    /// Whether [`Mipmap::flip_vertical`] can flip the mipmap. BC6H and BC7 cannot be flipped, and
+    /// neither can heights over 4 that are not a multiple of 4 in other block compressed formats
+    pub fn is_flippable(&self) -> bool {
+        use TextureFormat::*;
+        let (_, bh) = self.format.block_dims();
+        match self.format {
+            _ if self.height <= 1 => true,
+            BC7 | BC6H => false,
+            _ => self.height.is_multiple_of(bh) || self.height < bh,
+        }
+    }
+
+    /// Flips the mipmap upside down without decoding it.
+    ///
+    /// Mipmaps shorter than whole blocks, see [`TextureFormat::unpadded_len`], are padded with
+    /// zeroes first.
+    #[tracing::instrument(level = "trace", skip(self), fields(%self))]
+    pub fn flip_vertical(&mut self) -> Result<(), Error> {
+        let (width, height, format) = (self.width, self.height, self.format);
+        if !self.is_flippable() {
+            return Err(Error::Unflippable(self.to_string()));
+        }
+        if height <= 1 {
+            return Ok(());
+        }
+        let (bw, bh) = format.block_dims();
+        let len = format.data_len(width, height);
+        if self.data.len() < len {
+            if self.data.len() < format.unpadded_len(width, height) {
+                return Err(Error::TruncatedData {
+                    expected: len,
+                    available: self.data.len(),
+                });
+            }
+            self.data.to_mut().resize(len, 0);
+        }
+
+        let data = &mut self.data.to_mut()[..len];
+        flip_rows(data, width.div_ceil(bw) as usize * format.bytes_per_block());
+        if bh > 1 {
+            let rows = height.min(bh) as usize;
+            for block in data.chunks_exact_mut(format.bytes_per_block()) {
+                flip_block(format, block, rows);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reverses the order of the rows of `row_len` bytes in `data`
+pub(crate) fn flip_rows(data: &mut [u8], row_len: usize) {
+    let rows = data.len().checked_div(row_len).unwrap_or_default();
+    for y in 0..rows / 2 {
+        let (top, bottom) = data.split_at_mut((rows - 1 - y) * row_len);
+        top[y * row_len..][..row_len].swap_with_slice(&mut bottom[..row_len]);
+    }
+}
+
+/// Reverses the first `rows` rows of pixels of a block, the endpoints stay where they are
+fn flip_block(format: TextureFormat, block: &mut [u8], rows: usize) {
+    use TextureFormat::*;
+    match format {
+        DXT1 | DXT1a => flip_indices(&mut block[4..8], 8, rows),
+        DXT3 => {
+            flip_indices(&mut block[..8], 16, rows);
+            flip_indices(&mut block[12..16], 8, rows);
+        }
+        DXT5 => {
+            flip_indices(&mut block[2..8], 12, rows);
+            flip_indices(&mut block[12..16], 8, rows);
+        }
+        ATI1 => flip_indices(&mut block[2..8], 12, rows),
+        ATI2 => {
+            flip_indices(&mut block[2..8], 12, rows);
+            flip_indices(&mut block[10..16], 12, rows);
+        }
+        _ => unreachable!("{:?} blocks cannot be flipped", format),
+    }
+}
+
+/// Reverses the first `rows` rows of a little endian bit field with `bits` bits per row
+fn flip_indices(indices: &mut [u8], bits: usize, rows: usize) {
+    let mut buf = [0; 8];
+    buf[..indices.len()].copy_from_slice(indices);
+    let x = u64::from_le_bytes(buf);
+    let mask = (1 << bits) - 1;
+    let mut flipped = x;
+    for row in 0..rows {
+        let to = (rows - 1 - row) * bits;
+        flipped = flipped & !(mask << to) | (x >> (row * bits) & mask) << to;
+    }
+    indices.copy_from_slice(&flipped.to_le_bytes()[..indices.len()]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    fn decode(mip: &Mipmap, orientation: Orientation) -> Vec<u8> {
+        let mut rgba = vec![0; mip.width as usize * mip.height as usize * 4];
+        mip.decode_into(&mut rgba, PixelLayout::Rgba8, orientation)
+            .unwrap();
+        rgba
+    }
+
+    #[test]
+    fn flip_game_textures() {
+        let mut mips = vec![];
+        for bytes in [MIKITM, SPR] {
+            let atlas = TextureAtlas::from_bytes(bytes).unwrap();
+            let subtextures = atlas.textures.into_iter().flat_map(|x| x.subtextures);
+            mips.extend(subtextures.flat_map(|x| x.mipmaps));
+        }
+        for mip in mips.iter().filter(|x| x.width > 0 && x.height > 0) {
+            let mut flipped = mip.clone();
+            flipped.flip_vertical().unwrap();
+            assert_eq!(
+                decode(&flipped, Orientation::BottomUp),
+                decode(mip, Orientation::TopDown),
+                "{}",
+                mip
+            );
+            flipped.flip_vertical().unwrap();
+            assert_eq!(flipped.data[..mip.data.len()], mip.data[..]);
+        }
+    }
+
+    #[test]
+    fn unflippable() {
+        let mip = |format, height| Mipmap {
+            width: 4,
+            height,
+            format,
+            data: vec![0; format.data_len(4, height)].into(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            mip(TextureFormat::BC7, 4).flip_vertical(),
+            Err(Error::Unflippable(_))
+        ));
+        assert!(matches!(
+            mip(TextureFormat::DXT1, 6).flip_vertical(),
+            Err(Error::Unflippable(_))
+        ));
+        assert_eq!(mip(TextureFormat::BC6H, 1).flip_vertical(), Ok(()));
+        assert_eq!(mip(TextureFormat::RGB5, 3).flip_vertical(), Ok(()));
+    }
+}
@@ -1,88 +1,445 @@
-use ::image::dxt::{DXTVariant, DxtDecoder};
 use ::image::*;
 
 use super::*;
+use super::{encode, flip, resample};
 
+use std::convert::{TryFrom, TryInto};
 use std::path::Path;
 
+impl Subtexture<'_> {
+    /// Downsamples `base` with `filter` and encodes every level into `format` with `quality`.
+    ///
+    /// The chain has as many levels as the games use, it stops once the largest side reaches 2.
+    /// `max_levels` caps the number of levels, including the base level. `base` is flipped from
+    /// `orientation` to the way the games store textures.
+    ///
+    /// Sides are halved down to 1, while the games halve them down to 0: the last level of a
+    /// 256x64 texture is 2x1 here and 2x0 in the games. Levels without pixels cannot be encoded,
+    /// and [`TextureAtlas::validate`] accepts both.
+    pub fn generate_mipmaps(
+        base: &DynamicImage,
+        format: TextureFormat,
+        quality: Quality,
+        filter: MipFilter,
+        max_levels: Option<u32>,
+        orientation: Orientation,
+    ) -> Result<Subtexture<'static>, Error> {
+        let mut base = base.to_rgba8();
+        if orientation == Orientation::TopDown {
+            imageops::flip_vertical_in_place(&mut base);
+        }
+        let (width, height) = base.dimensions();
+        let levels =
+            resample::level_count(width, height).min(max_levels.unwrap_or(u32::MAX).max(1));
+        let premultiplied = resample::to_premultiplied(base.as_raw(), filter.gamma_correct);
+        let mipmaps = (0..levels)
+            .map(|level| {
+                let size = ((width >> level).max(1), (height >> level).max(1));
+                let rgba = if level == 0 {
+                    base.as_raw().clone()
+                } else {
+                    let rgba =
+                        resample::resize(&premultiplied, (width, height), size, filter.kernel);
+                    resample::from_premultiplied(&rgba, filter.gamma_correct)
+                };
+                let data = encode::encode(format, size.0, size.1, &rgba, quality)?;
+                Ok(Mipmap {
+                    id: level,
+                    width: size.0,
+                    height: size.1,
+                    format,
+                    data: data.into(),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Subtexture { mipmaps })
+    }
+}
+
 impl<'a> Mipmap<'a> {
-    pub fn to_dxt_decoder(&self) -> Option<Result<DxtDecoder<&[u8]>, ImageError>> {
-        use TextureFormat::*;
-        let format = match self.format {
-            DXT1 | DXT1a => Some(DXTVariant::DXT1),
-            DXT3 => Some(DXTVariant::DXT3),
-            DXT5 => Some(DXTVariant::DXT5),
-            _ => return None,
-        }?;
-        Some(DxtDecoder::new(&self.data, self.width, self.height, format))
+    /// Encodes `image` into `format`, block compressed formats are encoded with `quality`.
+    /// `image` is flipped from `orientation` to the way the games store textures.
+    pub fn from_image(
+        image: &DynamicImage,
+        format: TextureFormat,
+        quality: Quality,
+        orientation: Orientation,
+    ) -> Result<Mipmap<'static>, Error> {
+        let mut rgba = image.to_rgba8();
+        if orientation == Orientation::TopDown {
+            imageops::flip_vertical_in_place(&mut rgba);
+        }
+        let (width, height) = rgba.dimensions();
+        let data = encode::encode(format, width, height, rgba.as_raw(), quality)?;
+        Ok(Mipmap {
+            width,
+            height,
+            format,
+            data: data.into(),
+            ..Default::default()
+        })
     }
 
-    pub fn to_rgb(&self) -> Option<ImageBuffer<Rgb<u8>, &[u8]>> {
-        use TextureFormat::*;
-        match self.format {
-            RGB => ImageBuffer::from_raw(self.width, self.height, &self.data),
-            _ => None,
+    /// Borrows the data as an image if the mipmap is stored as `P`, and holds every pixel.
+    /// Views keep the rows in the order they are stored, bottom-up
+    fn view<P: Pixel<Subpixel = u8>>(
+        &self,
+        format: TextureFormat,
+    ) -> Option<ImageBuffer<P, &[u8]>> {
+        if self.format != format {
+            return None;
         }
+        let len = self.width as usize * self.height as usize * P::CHANNEL_COUNT as usize;
+        ImageBuffer::from_raw(self.width, self.height, self.data.get(..len)?)
+    }
+
+    /// Borrows an RGB8 mipmap
+    pub fn to_rgb(&self) -> Option<ImageBuffer<Rgb<u8>, &[u8]>> {
+        self.view(TextureFormat::RGB8)
     }
 
+    /// Borrows an RGBA8 mipmap
     pub fn to_rgba(&self) -> Option<ImageBuffer<Rgba<u8>, &[u8]>> {
-        use TextureFormat::*;
-        match self.format {
-            RGBA => ImageBuffer::from_raw(self.width, self.height, &self.data),
-            _ => None,
-        }
+        self.view(TextureFormat::RGBA8)
     }
 
+    /// Borrows an L8 mipmap
     pub fn to_luma(&self) -> Option<ImageBuffer<Luma<u8>, &[u8]>> {
-        use TextureFormat::*;
-        match self.format {
-            L8 => ImageBuffer::from_raw(self.width, self.height, &self.data),
-            _ => None,
-        }
+        self.view(TextureFormat::L8)
     }
 
+    /// Borrows an L8A8 mipmap
     pub fn to_luma_alpha(&self) -> Option<ImageBuffer<LumaA<u8>, &[u8]>> {
+        self.view(TextureFormat::L8A8)
+    }
+
+    /// Decodes any format other than BC6H into top-down RGBA8
+    pub fn to_rgba8(&self) -> Option<RgbaImage> {
+        let rgba = self.decode_rgba8().ok()?;
+        ImageBuffer::from_raw(self.width, self.height, rgba)
+    }
+
+    /// Decodes BC6H into top-down RGBA32F
+    pub fn to_rgba32f(&self) -> Option<Rgba32FImage> {
         match self.format {
-            L8A8 => ImageBuffer::from_raw(self.width, self.height, &self.data),
+            TextureFormat::BC6H => self.decode_rgba32f(Orientation::default()).ok(),
             _ => None,
         }
     }
 
-    pub fn to_dynamic_image(self) -> Option<DynamicImage> {
+    fn decode_rgba32f(&self, orientation: Orientation) -> Result<Rgba32FImage, Error> {
+        let mut bytes = vec![0; self.width as usize * self.height as usize * 16];
+        self.decode_into(&mut bytes, PixelLayout::Rgba32F, orientation)?;
+        let rgba = bytes
+            .chunks_exact(4)
+            .map(|x| f32::from_ne_bytes(x.try_into().unwrap()))
+            .collect();
+        Ok(ImageBuffer::from_raw(self.width, self.height, rgba)
+            .expect("a pixel is decoded per pixel of the mipmap"))
+    }
+
+    /// Copies the formats the image crate has a pixel type for, and decodes every other format.
+    /// BC6H is decoded to RGBA32F, the rest to RGBA8
+    pub fn to_image(&self, orientation: Orientation) -> Result<DynamicImage, Error> {
         use TextureFormat::*;
-        match self.format {
-            RGB => ImageBuffer::from_raw(self.width, self.height, self.data.into_owned())
+        let owned = |raw: &[u8], channels: usize| {
+            let mut raw = raw.to_vec();
+            if orientation == Orientation::TopDown {
+                flip::flip_rows(&mut raw, self.width as usize * channels);
+            }
+            raw
+        };
+        let (width, height) = (self.width, self.height);
+        let copied = match self.format {
+            RGB8 => self
+                .to_rgb()
+                .and_then(|x| ImageBuffer::from_raw(width, height, owned(&x, 3)))
                 .map(DynamicImage::ImageRgb8),
-            RGBA => ImageBuffer::from_raw(self.width, self.height, self.data.into_owned())
+            RGBA8 => self
+                .to_rgba()
+                .and_then(|x| ImageBuffer::from_raw(width, height, owned(&x, 4)))
                 .map(DynamicImage::ImageRgba8),
-            L8 => ImageBuffer::from_raw(self.width, self.height, self.data.into_owned())
+            L8 => self
+                .to_luma()
+                .and_then(|x| ImageBuffer::from_raw(width, height, owned(&x, 1)))
                 .map(DynamicImage::ImageLuma8),
-            L8A8 => ImageBuffer::from_raw(self.width, self.height, self.data.into_owned())
+            L8A8 => self
+                .to_luma_alpha()
+                .and_then(|x| ImageBuffer::from_raw(width, height, owned(&x, 2)))
                 .map(DynamicImage::ImageLumaA8),
-            DXT1 | DXT1a | DXT3 | DXT5 => {
-                let dec = self.to_dxt_decoder()?.ok()?;
-                DynamicImage::from_decoder(dec).ok()
+            BC6H => {
+                return self
+                    .decode_rgba32f(orientation)
+                    .map(DynamicImage::ImageRgba32F)
             }
-            _ => return None,
+            _ => None,
+        };
+        if let Some(image) = copied {
+            return Ok(image);
         }
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        self.decode_into(&mut rgba, PixelLayout::Rgba8, orientation)?;
+        let image = ImageBuffer::from_raw(width, height, rgba);
+        Ok(DynamicImage::ImageRgba8(
+            image.expect("a pixel is decoded per pixel of the mipmap"),
+        ))
     }
 
-    pub fn save<Q>(&self, path: Q) -> Option<ImageResult<()>>
+    /// Saves the mipmap, BC6H is saved as 16 bits per channel as few formats store floats
+    ///
+    /// Fails with the errors of [`Mipmap::to_image`], or [`Error::Image`] when the image cannot be
+    /// written
+    pub fn save<Q>(&self, path: Q, orientation: Orientation) -> Result<(), Error>
     where
         Q: AsRef<Path>,
     {
+        let image = self.to_image(orientation)?;
+        match image {
+            DynamicImage::ImageRgba32F(_) => image.to_rgba16().save(path),
+            _ => image.save(path),
+        }
+        .map_err(|e| Error::Image(e.to_string()))
+    }
+}
+
+/// Converts a mipmap into a top-down image, see [`Mipmap::to_image`]
+impl TryFrom<&Mipmap<'_>> for DynamicImage {
+    type Error = Error;
+
+    fn try_from(mip: &Mipmap<'_>) -> Result<Self, Error> {
+        mip.to_image(Orientation::default())
+    }
+}
+
+/// Stores 8 bit RGB, RGBA, luma and luma alpha images as is, every other image as RGBA8, so 16 bit
+/// images lose their low bits. The image is taken to be top-down. Floating point images are
+/// refused with [`Error::Unrepresentable`], as there is no BC6H encoder
+impl TryFrom<&DynamicImage> for Mipmap<'static> {
+    type Error = Error;
+
+    fn try_from(image: &DynamicImage) -> Result<Self, Error> {
         use TextureFormat::*;
-        Some(match self.format {
-            RGB => self.to_rgb()?.save(path),
-            RGBA => self.to_rgba()?.save(path),
-            L8 => self.to_luma()?.save(path),
-            L8A8 => self.to_luma_alpha()?.save(path),
-            DXT1 | DXT1a | DXT3 | DXT5 => {
-                let dec = self.to_dxt_decoder()?.ok()?;
-                let image = DynamicImage::from_decoder(dec).ok()?;
-                image.save(path)
+        let image = &image.flipv();
+        let (format, data) = match image {
+            DynamicImage::ImageRgb8(x) => (RGB8, x.as_raw().clone()),
+            DynamicImage::ImageRgba8(x) => (RGBA8, x.as_raw().clone()),
+            DynamicImage::ImageLuma8(x) => (L8, x.as_raw().clone()),
+            DynamicImage::ImageLumaA8(x) => (L8A8, x.as_raw().clone()),
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                return Err(Error::Unrepresentable(format!(
+                    "{:?} pixels",
+                    image.color()
+                )))
             }
-            _ => return None,
+            _ => (RGBA8, image.to_rgba8().into_raw()),
+        };
+        Ok(Mipmap {
+            width: image.width(),
+            height: image.height(),
+            format,
+            data: data.into(),
+            ..Default::default()
         })
     }
 }
+
+impl Texture<'_> {
+    /// Decodes the base level of the first subtexture, the two levels of YUV textures are combined
+    fn to_image(&self, orientation: Orientation) -> Result<DynamicImage, Error> {
+        if self.is_yuv() {
+            return self.subtextures[0]
+                .yuv_to_image(orientation)
+                .map(DynamicImage::ImageRgba8);
+        }
+        let mip = self
+            .subtextures
+            .first()
+            .and_then(|x| x.mipmaps.first())
+            .ok_or_else(|| Error::Undecodable("a texture without mipmaps".into()))?;
+        mip.to_image(orientation)
+    }
+}
+
+impl SpriteSet<'_> {
+    /// Decodes the texture of the sprite named `name` top-down and crops it to the sprite
+    pub fn sprite_image(&self, name: &str) -> Result<DynamicImage, Error> {
+        let sprite = self
+            .sprites
+            .iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| Error::UnknownSprite(name.into()))?;
+        let texture = self
+            .atlas
+            .textures
+            .get(sprite.texture_index as usize)
+            .ok_or(Error::MissingTexture(sprite.texture_index))?;
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = sprite.rect;
+        let image = texture.to_image(Orientation::TopDown)?;
+        let px = |x: f32| x.round().max(0.0) as u32;
+        Ok(image.crop_imm(px(x), px(y), px(width), px(height)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    #[test]
+    fn crop_sprite() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let rect = Rect {
+            x: 10.0,
+            y: 20.0,
+            width: 64.0,
+            height: 32.0,
+        };
+        let sprite = Sprite::new("JACKET", 1, rect, (2048, 1024));
+        let set = SpriteSet::new(atlas, vec![], vec![sprite]);
+
+        let image = set.sprite_image("JACKET").unwrap();
+        assert_eq!(image.dimensions(), (64, 32));
+        let page = set.atlas.textures[1]
+            .to_image(Orientation::TopDown)
+            .unwrap();
+        assert_eq!(image.to_rgba8(), page.crop_imm(10, 20, 64, 32).to_rgba8());
+
+        assert_eq!(
+            set.sprite_image("LOGO").unwrap_err(),
+            Error::UnknownSprite("LOGO".into())
+        );
+    }
+
+    #[test]
+    fn mipmaps_with_quality() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let dxt5 = &atlas.textures[1].subtextures[0].mipmaps[0];
+        let image = DynamicImage::try_from(dxt5)
+            .unwrap()
+            .crop_imm(100, 100, 64, 32);
+        let format = TextureFormat::DXT1;
+        let mut encoded = vec![];
+        for quality in [Quality::Fast, Quality::Best] {
+            let orientation = Orientation::TopDown;
+            let filter = MipFilter::default();
+            let subtex =
+                Subtexture::generate_mipmaps(&image, format, quality, filter, None, orientation)
+                    .unwrap();
+            let base = Mipmap::from_image(&image, format, quality, orientation).unwrap();
+            assert_eq!(subtex.mipmaps[0].data, base.data, "{:?}", quality);
+            encoded.push(subtex);
+        }
+        assert_ne!(encoded[0], encoded[1]);
+    }
+
+    #[test]
+    fn borrowed_views() {
+        let mip = Mipmap {
+            width: 2,
+            height: 2,
+            format: TextureFormat::RGB8,
+            data: (0..13).collect::<Vec<u8>>().into(),
+            ..Default::default()
+        };
+        let view = mip.to_rgb().unwrap();
+        assert_eq!(view.as_raw().len(), 12);
+        assert_eq!(view.get_pixel(1, 1), &Rgb([9, 10, 11]));
+        assert!(mip.to_rgba().is_none());
+        assert!(mip.to_luma().is_none());
+        assert!(mip.to_luma_alpha().is_none());
+        let image = mip.to_image(Orientation::TopDown).unwrap();
+        assert_eq!(image.to_rgb8().get_pixel(1, 0), &Rgb([9, 10, 11]));
+        let image = mip.to_image(Orientation::BottomUp).unwrap();
+        assert_eq!(image.to_rgb8().get_pixel(1, 1), &Rgb([9, 10, 11]));
+
+        let truncated = Mipmap {
+            data: vec![0; 11].into(),
+            ..mip.clone()
+        };
+        assert!(truncated.to_rgb().is_none());
+        assert!(matches!(
+            DynamicImage::try_from(&truncated),
+            Err(Error::TruncatedData { .. })
+        ));
+    }
+
+    #[test]
+    fn convert_dynamic_images() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let dxt5 = &atlas.textures[1].subtextures[0].mipmaps[0];
+        let image = DynamicImage::try_from(dxt5).unwrap();
+        assert_eq!(image.color(), ColorType::Rgba8);
+        assert_eq!(image.dimensions(), (dxt5.width, dxt5.height));
+
+        let mip = Mipmap::try_from(&image).unwrap();
+        assert_eq!(mip.format, TextureFormat::RGBA8);
+        assert_eq!(*mip.to_rgba().unwrap().as_raw(), image.flipv().as_bytes());
+        assert_eq!(mip.to_image(Orientation::TopDown).unwrap(), image);
+
+        for image in [
+            DynamicImage::ImageRgb8(image.to_rgb8()),
+            DynamicImage::ImageLuma8(image.to_luma8()),
+            DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ] {
+            let mip = Mipmap::try_from(&image).unwrap();
+            assert_eq!(DynamicImage::try_from(&mip).unwrap(), image);
+        }
+        let rgb16 = DynamicImage::ImageRgb16(image.to_rgb16());
+        let mip = Mipmap::try_from(&rgb16).unwrap();
+        assert_eq!(mip.format, TextureFormat::RGBA8);
+        let image8 = DynamicImage::ImageRgba8(rgb16.to_rgba8());
+        assert_eq!(mip.to_image(Orientation::TopDown).unwrap(), image8);
+        let hdr = DynamicImage::ImageRgba32F(image.to_rgba32f());
+        assert_eq!(
+            Mipmap::try_from(&hdr),
+            Err(Error::Unrepresentable("Rgba32F pixels".into()))
+        );
+    }
+
+    #[test]
+    fn save_errors() {
+        let path = std::env::temp_dir().join("txp_save_errors.png");
+        let truncated = Mipmap {
+            width: 4,
+            height: 4,
+            format: TextureFormat::DXT5,
+            data: vec![0; 8].into(),
+            ..Default::default()
+        };
+        let err = truncated.to_image(Orientation::TopDown).unwrap_err();
+        assert_eq!(truncated.save(&path, Orientation::TopDown), Err(err));
+
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let mip = &atlas.textures[1].subtextures[0].mipmaps[0];
+        assert!(matches!(
+            mip.save(path.with_extension("unknown"), Orientation::TopDown),
+            Err(Error::Image(_))
+        ));
+    }
+
+    #[test]
+    fn mipmap_sizes() {
+        let base = DynamicImage::new_rgba8(16, 4);
+        let sub = Subtexture::generate_mipmaps(
+            &base,
+            TextureFormat::RGBA8,
+            Quality::default(),
+            MipFilter::default(),
+            None,
+            Orientation::default(),
+        )
+        .unwrap();
+        let sizes: Vec<_> = sub.mipmaps.iter().map(|x| (x.width, x.height)).collect();
+        // the games would end the chain with 2x0
+        assert_eq!(sizes, [(16, 4), (8, 2), (4, 1), (2, 1)]);
+        let atlas = TextureAtlas::new(vec![Texture::new(vec![sub])]);
+        assert_eq!(atlas.validate(), []);
+    }
+}
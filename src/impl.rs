@@ -1,6 +1,183 @@
 use super::*;
 use std::fmt::Display;
 
+impl<'a> TextureAtlas<'a> {
+    pub fn new(textures: Vec<Texture<'a>>) -> Self {
+        let count = textures.len() as u32;
+        Self {
+            textures,
+            revision: Revision::default(),
+            endianness: Endianness::default(),
+            unk: 0x0101_0100 | (count & 0xFF),
+            section: None,
+        }
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Changes the byte order of the atlas and everything in it
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+        for tex in &mut self.textures {
+            tex.set_endianness(endianness);
+        }
+    }
+
+    /// The header word following the texture count.
+    ///
+    /// The low byte is the texture count again, it is updated when the atlas is written
+    pub fn unk(&self) -> u32 {
+        self.unk
+    }
+}
+
+impl<'a> Texture<'a> {
+    /// A 2D texture from a single subtexture, or an array of several
+    pub fn new(subtextures: Vec<Subtexture<'a>>) -> Self {
+        let kind = if subtextures.len() > 1 {
+            TextureKind::Array
+        } else {
+            TextureKind::TwoD
+        };
+        Self::with_kind(subtextures, kind)
+    }
+
+    /// A cubemap from its faces, in the order of [`Face`]
+    pub fn new_cube(faces: [Subtexture<'a>; 6]) -> Self {
+        Self::with_kind(faces.into(), TextureKind::Cube)
+    }
+
+    fn with_kind(subtextures: Vec<Subtexture<'a>>, kind: TextureKind) -> Self {
+        let depth = subtextures.len().max(1) as u32;
+        let mip_count = subtextures
+            .first()
+            .map(|x| x.mipmaps.len())
+            .unwrap_or_default() as u32;
+        Self {
+            subtextures,
+            kind,
+            id: None,
+            name: None,
+            endianness: Endianness::default(),
+            unk: 0x0101_0000 | ((depth & 0xFF) << 8) | (mip_count & 0xFF),
+        }
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Changes the byte order of the texture and its mipmaps
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+        for mip in self
+            .subtextures
+            .iter_mut()
+            .flat_map(|x| x.mipmaps.iter_mut())
+        {
+            mip.endianness = endianness;
+        }
+    }
+
+    /// The subtexture holding `face` of a cubemap, `None` for other kinds of textures
+    pub fn cube_face(&self, face: Face) -> Option<&Subtexture<'a>> {
+        match self.kind {
+            TextureKind::Cube => self.subtextures.get(face as usize),
+            _ => None,
+        }
+    }
+
+    pub fn cube_face_mut(&mut self, face: Face) -> Option<&mut Subtexture<'a>> {
+        match self.kind {
+            TextureKind::Cube => self.subtextures.get_mut(face as usize),
+            _ => None,
+        }
+    }
+
+    /// The header word following the mipmap count.
+    ///
+    /// For arrays, the second byte is the depth of the array
+    pub fn unk(&self) -> u32 {
+        self.unk
+    }
+}
+
+impl Face {
+    /// Every face, in storage order
+    pub const ALL: [Face; 6] = [
+        Face::PositiveX,
+        Face::NegativeX,
+        Face::PositiveY,
+        Face::NegativeY,
+        Face::PositiveZ,
+        Face::NegativeZ,
+    ];
+}
+
+impl Mipmap<'_> {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn set_id(&mut self, id: u32) {
+        self.id = id;
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    /// Size in bytes the data should have for the dimensions and format of the mipmap
+    pub fn expected_len(&self) -> usize {
+        self.format.data_len(self.width, self.height)
+    }
+}
+
+impl TextureFormat {
+    /// Width and height of a block, uncompressed formats have 1x1 blocks
+    pub fn block_dims(self) -> (u32, u32) {
+        use TextureFormat::*;
+        match self {
+            DXT1 | DXT1a | DXT3 | DXT5 | ATI1 | ATI2 | BC7 | BC6H => (4, 4),
+            _ => (1, 1),
+        }
+    }
+
+    /// Size in bytes of a block, or a pixel for uncompressed formats
+    pub fn bytes_per_block(self) -> usize {
+        use TextureFormat::*;
+        match self {
+            A8 | L8 => 1,
+            RGB5 | RGB5A1 | RGBA4 | L8A8 => 2,
+            RGB8 => 3,
+            RGBA8 => 4,
+            DXT1 | DXT1a | ATI1 => 8,
+            DXT3 | DXT5 | ATI2 | BC7 | BC6H => 16,
+        }
+    }
+
+    /// Number of bytes a `width`x`height` image takes in this format, in whole blocks
+    pub fn data_len(self, width: u32, height: u32) -> usize {
+        let (bw, bh) = self.block_dims();
+        let blocks = (width.div_ceil(bw) as usize).saturating_mul(height.div_ceil(bh) as usize);
+        blocks.saturating_mul(self.bytes_per_block())
+    }
+
+    /// The size the games store for a `width`x`height` image.
+    ///
+    /// The games size mipmaps by their number of pixels instead of their number of blocks, so the
+    /// smallest mipmaps of block compressed formats are shorter than the whole blocks of
+    /// [`TextureFormat::data_len`]. DDS, KTX2 and the decoders need whole blocks, these mipmaps are
+    /// padded with zeroes on the way out and trimmed back to this size when importing DDS files.
+    pub fn unpadded_len(self, width: u32, height: u32) -> usize {
+        let (bw, bh) = self.block_dims();
+        let bits = self.bytes_per_block() * 8 / (bw * bh) as usize;
+        (width as usize * height as usize * bits / 8).max(1)
+    }
+}
+
 impl Display for Mipmap<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "SubTex {}x{} {:?}", self.width, self.height, self.format)
@@ -0,0 +1,778 @@
+//! KTX2 import and export, see [`Texture::from_ktx2`] and [`Texture::to_ktx2`].
+//!
+//! The file starts with the KTX2 identifier and a little endian header, followed by the index of
+//! the levels, the data format descriptor, the key/value data, then the levels from the smallest
+//! to the largest. Every level holds the image of each layer, or of each face of a cubemap.
+use std::convert::TryInto;
+
+use nom::number::complete::{le_u32, le_u64};
+use tracing::debug;
+
+use super::read::{take_exact, IResult};
+use super::*;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+/// Identifier, header and the index of the data format descriptor, key/value and global data
+const HEADER_LEN: usize = 80;
+const LEVEL_INDEX_LEN: usize = 24;
+
+/// Models of the data format descriptor
+const MODEL_RGBSDA: u8 = 1;
+const MODEL_BC1A: u8 = 128;
+const MODEL_BC2: u8 = 129;
+const MODEL_BC3: u8 = 130;
+const MODEL_BC4: u8 = 131;
+const MODEL_BC5: u8 = 132;
+const MODEL_BC6H: u8 = 133;
+const MODEL_BC7: u8 = 134;
+
+const CHANNEL_RED: u8 = 0;
+const CHANNEL_GREEN: u8 = 1;
+const CHANNEL_BLUE: u8 = 2;
+const CHANNEL_ALPHA: u8 = 15;
+const QUALIFIER_FLOAT: u8 = 0x80;
+
+/// A sample of the data format descriptor, as `(bit offset, bit length, channel, lower, upper)`
+type Sample = (u16, u8, u8, u32, u32);
+
+impl TextureFormat {
+    /// The `VkFormat` of the data, the luminance and alpha formats are stored in red and green
+    pub fn to_vk_format(self) -> u32 {
+        use TextureFormat::*;
+        match self {
+            A8 | L8 => 9,           // R8_UNORM
+            L8A8 => 16,             // R8G8_UNORM
+            RGB8 => 23,             // R8G8B8_UNORM
+            RGBA8 => 37,            // R8G8B8A8_UNORM
+            RGB5 => 4,              // R5G6B5_UNORM_PACK16
+            RGB5A1 => 8,            // A1R5G5B5_UNORM_PACK16
+            RGBA4 => 1_000_340_000, // A4R4G4B4_UNORM_PACK16
+            DXT1 => 131,            // BC1_RGB_UNORM_BLOCK
+            DXT1a => 133,           // BC1_RGBA_UNORM_BLOCK
+            DXT3 => 135,            // BC2_UNORM_BLOCK
+            DXT5 => 137,            // BC3_UNORM_BLOCK
+            ATI1 => 139,            // BC4_UNORM_BLOCK
+            ATI2 => 141,            // BC5_UNORM_BLOCK
+            BC6H => 143,            // BC6H_UFLOAT_BLOCK
+            BC7 => 145,             // BC7_UNORM_BLOCK
+        }
+    }
+
+    /// The inverse of [`TextureFormat::to_vk_format`], the sRGB formats are read as their UNORM
+    /// counterpart and `R8_UNORM` maps to [`TextureFormat::L8`]
+    #[tracing::instrument(level = "trace", ret)]
+    pub fn from_vk_format(format: u32) -> Option<Self> {
+        use TextureFormat::*;
+        let format = match format {
+            9 => L8,
+            16 => L8A8,
+            23 | 29 => RGB8,
+            37 | 43 => RGBA8,
+            4 => RGB5,
+            8 => RGB5A1,
+            1_000_340_000 => RGBA4,
+            131 | 132 => DXT1,
+            133 | 134 => DXT1a,
+            135 | 136 => DXT3,
+            137 | 138 => DXT5,
+            139 => ATI1,
+            141 => ATI2,
+            143 => BC6H,
+            145 | 146 => BC7,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// How a reader should map the channels of the `VkFormat` back to RGBA
+    fn ktx_swizzle(self) -> Option<&'static str> {
+        use TextureFormat::*;
+        match self {
+            A8 => Some("000r"),
+            L8 => Some("rrr1"),
+            L8A8 => Some("rrrg"),
+            _ => None,
+        }
+    }
+
+    /// The model and samples of the data format descriptor
+    fn ktx_samples(self) -> (u8, Vec<Sample>) {
+        use TextureFormat::*;
+        let unorm = |offset, bits: u8, channel| (offset, bits, channel, 0, (1 << bits) - 1);
+        let block = |offset, bits, channel| (offset, bits, channel, 0, u32::MAX);
+        match self {
+            A8 | L8 => (MODEL_RGBSDA, vec![unorm(0, 8, CHANNEL_RED)]),
+            L8A8 => (
+                MODEL_RGBSDA,
+                vec![unorm(0, 8, CHANNEL_RED), unorm(8, 8, CHANNEL_GREEN)],
+            ),
+            RGB8 | RGBA8 => {
+                let mut samples = vec![
+                    unorm(0, 8, CHANNEL_RED),
+                    unorm(8, 8, CHANNEL_GREEN),
+                    unorm(16, 8, CHANNEL_BLUE),
+                ];
+                if self == RGBA8 {
+                    samples.push(unorm(24, 8, CHANNEL_ALPHA));
+                }
+                (MODEL_RGBSDA, samples)
+            }
+            RGB5 => (
+                MODEL_RGBSDA,
+                vec![
+                    unorm(0, 5, CHANNEL_BLUE),
+                    unorm(5, 6, CHANNEL_GREEN),
+                    unorm(11, 5, CHANNEL_RED),
+                ],
+            ),
+            RGB5A1 => (
+                MODEL_RGBSDA,
+                vec![
+                    unorm(0, 5, CHANNEL_BLUE),
+                    unorm(5, 5, CHANNEL_GREEN),
+                    unorm(10, 5, CHANNEL_RED),
+                    unorm(15, 1, CHANNEL_ALPHA),
+                ],
+            ),
+            RGBA4 => (
+                MODEL_RGBSDA,
+                vec![
+                    unorm(0, 4, CHANNEL_BLUE),
+                    unorm(4, 4, CHANNEL_GREEN),
+                    unorm(8, 4, CHANNEL_RED),
+                    unorm(12, 4, CHANNEL_ALPHA),
+                ],
+            ),
+            // the second channel of BC1A is the presence of punchthrough alpha
+            DXT1 => (MODEL_BC1A, vec![block(0, 64, 0)]),
+            DXT1a => (MODEL_BC1A, vec![block(0, 64, 1)]),
+            DXT3 | DXT5 => (
+                if self == DXT3 { MODEL_BC2 } else { MODEL_BC3 },
+                vec![block(0, 64, CHANNEL_ALPHA), block(64, 64, 0)],
+            ),
+            ATI1 => (MODEL_BC4, vec![block(0, 64, 0)]),
+            ATI2 => (MODEL_BC5, vec![block(0, 64, 0), block(64, 64, 1)]),
+            BC6H => (
+                MODEL_BC6H,
+                // -1.0 to 1.0
+                vec![(0, 128, QUALIFIER_FLOAT, 0xBF80_0000, 0x3F80_0000)],
+            ),
+            BC7 => (MODEL_BC7, vec![block(0, 128, 0)]),
+        }
+    }
+
+    /// The basic data format descriptor, prefixed by its total size
+    fn ktx_dfd(self) -> Vec<u8> {
+        let (model, samples) = self.ktx_samples();
+        let (bw, bh) = self.block_dims();
+        let block_len = 24 + 16 * samples.len();
+        let mut dfd = vec![];
+        dfd.extend((4 + block_len as u32).to_le_bytes());
+        // vendor and descriptor type, then the version and the size of the block
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(2u16.to_le_bytes());
+        dfd.extend((block_len as u16).to_le_bytes());
+        // BT.709 primaries, linear transfer function and straight alpha
+        dfd.extend([model, 1, 1, 0]);
+        dfd.extend([bw as u8 - 1, bh as u8 - 1, 0, 0]);
+        dfd.extend([self.bytes_per_block() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for (offset, bits, channel, lower, upper) in samples {
+            dfd.extend(offset.to_le_bytes());
+            dfd.extend([bits - 1, channel, 0, 0, 0, 0]);
+            dfd.extend(lower.to_le_bytes());
+            dfd.extend(upper.to_le_bytes());
+        }
+        dfd
+    }
+}
+
+/// The key/value data, every pair is prefixed by its length and padded to 4 bytes
+fn ktx_kvd(pairs: &[(&str, &str)]) -> Vec<u8> {
+    let mut kvd = vec![];
+    for (key, value) in pairs {
+        let len = key.len() + value.len() + 2;
+        kvd.extend((len as u32).to_le_bytes());
+        kvd.extend(key.as_bytes());
+        kvd.push(0);
+        kvd.extend(value.as_bytes());
+        kvd.push(0);
+        kvd.resize(kvd.len().next_multiple_of(4), 0);
+    }
+    kvd
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    a / gcd(a, b) * b
+}
+
+/// The header fields of a KTX2 file that matter to a [`Texture`]
+#[derive(Debug)]
+struct Header {
+    format: u32,
+    width: u32,
+    height: u32,
+    depth: u32,
+    layers: u32,
+    faces: u32,
+    levels: u32,
+    supercompression: u32,
+    kvd_offset: u32,
+    kvd_len: u32,
+}
+
+fn parse_header(i: &[u8]) -> IResult<&[u8], Header> {
+    let (i, format) = le_u32(i)?;
+    let (i, _type_size) = le_u32(i)?;
+    let (i, width) = le_u32(i)?;
+    let (i, height) = le_u32(i)?;
+    let (i, depth) = le_u32(i)?;
+    let (i, layers) = le_u32(i)?;
+    let (i, faces) = le_u32(i)?;
+    let (i, levels) = le_u32(i)?;
+    let (i, supercompression) = le_u32(i)?;
+    // the data format descriptor is implied by the format
+    let (i, _dfd) = take_exact(8)(i)?;
+    let (i, kvd_offset) = le_u32(i)?;
+    let (i, kvd_len) = le_u32(i)?;
+    let (i, _sgd) = take_exact(16)(i)?;
+    let header = Header {
+        format,
+        width,
+        height,
+        depth,
+        layers,
+        faces,
+        levels,
+        supercompression,
+        kvd_offset,
+        kvd_len,
+    };
+    Ok((i, header))
+}
+
+/// `len` bytes at `offset` of `i0`
+fn slice(i0: &[u8], offset: u64, len: u64) -> Result<&[u8], Error> {
+    let out_of_bounds = || Error::OffsetOutOfBounds {
+        offset: offset as usize,
+        len: i0.len(),
+    };
+    let start: usize = offset.try_into().map_err(|_| out_of_bounds())?;
+    let data = i0.get(start..).ok_or_else(out_of_bounds)?;
+    let len = len.try_into().unwrap_or(usize::MAX);
+    data.get(..len).ok_or(Error::TruncatedData {
+        expected: len,
+        available: data.len(),
+    })
+}
+
+/// The value of `key` in the key/value data, without its terminating NUL
+fn kvd_value<'a>(mut kvd: &'a [u8], key: &str) -> Option<&'a [u8]> {
+    while kvd.len() >= 4 {
+        let len = u32::from_le_bytes(kvd[..4].try_into().unwrap()) as usize;
+        let pair = kvd.get(4..4 + len)?;
+        let mut split = pair.splitn(2, |&x| x == 0);
+        if split.next() == Some(key.as_bytes()) {
+            let value = split.next()?;
+            return Some(value.strip_suffix(&[0]).unwrap_or(value));
+        }
+        kvd = kvd.get((4 + len).next_multiple_of(4)..)?;
+    }
+    None
+}
+
+impl Texture<'_> {
+    /// Reads a KTX2 file, every layer or cube face becomes a subtexture.
+    ///
+    /// Only formats TXP can store are accepted, supercompressed files, volume textures and cubemap
+    /// arrays are rejected. Files are flipped to the way the games store textures unless they are
+    /// already bottom-up. `None` reads the orientation from the `KTXorientation` of the file and
+    /// keeps textures that cannot be flipped as they are, `Some` overrides it.
+    pub fn from_ktx2(
+        input: &[u8],
+        orientation: Option<Orientation>,
+    ) -> Result<Texture<'static>, Error> {
+        let (i, identifier) = take_exact(IDENTIFIER.len())(input)?;
+        if identifier != IDENTIFIER {
+            return Err(Error::BadMagic {
+                found: identifier[..4].try_into().unwrap(),
+            });
+        }
+        let (i, header) = parse_header(i)?;
+        debug!(?header);
+        let format = TextureFormat::from_vk_format(header.format)
+            .ok_or_else(|| Error::Unrepresentable(format!("VkFormat {}", header.format)))?;
+        if header.supercompression != 0 {
+            return Err(Error::Unrepresentable(format!(
+                "supercompression scheme {}",
+                header.supercompression
+            )));
+        }
+        if header.depth > 1 {
+            return Err(Error::Unrepresentable("volume textures".into()));
+        }
+        if header.faces != 1 && header.faces != 6 {
+            return Err(Error::Unrepresentable(format!(
+                "{} cubemap faces",
+                header.faces
+            )));
+        }
+        if header.faces == 6 && header.layers > 1 {
+            return Err(Error::Unrepresentable("cubemap arrays".into()));
+        }
+        if header.layers == 6 {
+            return Err(Error::Unrepresentable("an array of 6 layers".into()));
+        }
+        let kvd = slice(input, header.kvd_offset.into(), header.kvd_len.into())?;
+        let format = match kvd_value(kvd, "KTXswizzle") {
+            Some(b"000r") if format == TextureFormat::L8 => TextureFormat::A8,
+            _ => format,
+        };
+
+        let images = header
+            .layers
+            .max(1)
+            .checked_mul(header.faces)
+            .ok_or(Error::CountOverflow(header.layers))?;
+        let levels = header.levels.max(1);
+        // the sides of a texture cannot be halved more than 32 times
+        if levels > 32 || (levels as usize).saturating_mul(LEVEL_INDEX_LEN) > i.len() {
+            return Err(Error::CountOverflow(levels));
+        }
+        // allocated once the first level is known to hold every image
+        let mut subtextures = vec![];
+        let mut i = i;
+        for level in 0..levels {
+            let (rest, offset) = le_u64(i)?;
+            let (rest, len) = le_u64(rest)?;
+            let (rest, _uncompressed_len) = le_u64(rest)?;
+            i = rest;
+            let width = (header.width >> level).max(1);
+            let height = (header.height >> level).max(1);
+            let image_len = format.data_len(width, height);
+            let data = slice(input, offset, len)?;
+            let expected = image_len.saturating_mul(images as usize);
+            if data.len() < expected {
+                return Err(Error::TruncatedData {
+                    expected,
+                    available: data.len(),
+                });
+            }
+            if level == 0 {
+                subtextures = vec![Subtexture { mipmaps: vec![] }; images as usize];
+            }
+            for (subtex, image) in subtextures.iter_mut().zip(data.chunks_exact(image_len)) {
+                let mut mip = Mipmap {
+                    width,
+                    height,
+                    format,
+                    data: image.to_vec().into(),
+                    ..Default::default()
+                };
+                mip.set_id(level);
+                subtex.mipmaps.push(mip);
+            }
+        }
+        let mut tex = Texture::new(subtextures);
+        tex.kind = match (header.faces, header.layers) {
+            (6, _) => TextureKind::Cube,
+            (_, 0) => TextureKind::TwoD,
+            _ => TextureKind::Array,
+        };
+        // the rows go down unless the second letter is `u`
+        let orientation = orientation.or_else(|| {
+            let bottom_up =
+                kvd_value(kvd, "KTXorientation").is_some_and(|x| x.get(1) == Some(&b'u'));
+            bottom_up.then_some(Orientation::BottomUp)
+        });
+        tex.flip_to(orientation)?;
+        Ok(tex)
+    }
+}
+
+impl Texture<'_> {
+    /// Writes the texture as KTX2, with every subtexture as a layer, or as a face of a cubemap.
+    ///
+    /// Mipmaps are padded to whole blocks, see [`TextureFormat::unpadded_len`]. The texture is
+    /// flipped to `orientation`, which is recorded as the `KTXorientation` of the file. Textures
+    /// that cannot be flipped (see [`Texture::is_flippable`]) are written bottom-up instead.
+    pub fn to_ktx2(&self, orientation: Orientation) -> Result<Vec<u8>, Error> {
+        let mut vec = vec![];
+        match orientation {
+            Orientation::TopDown if !self.is_flippable() => {
+                debug!("the texture cannot be flipped, it is written bottom-up");
+                self.write_ktx2(&mut vec, Orientation::BottomUp)?
+            }
+            Orientation::TopDown => {
+                let mut flipped = self.clone();
+                flipped.flip_vertical()?;
+                flipped.write_ktx2(&mut vec, orientation)?;
+            }
+            Orientation::BottomUp => self.write_ktx2(&mut vec, orientation)?,
+        }
+        Ok(vec)
+    }
+
+    #[tracing::instrument(skip_all)]
+    fn write_ktx2(&self, w: &mut Vec<u8>, orientation: Orientation) -> Result<(), Error> {
+        let first = self
+            .subtextures
+            .first()
+            .and_then(|x| x.mipmaps.first())
+            .ok_or_else(|| Error::Unrepresentable("a texture without mipmaps".into()))?;
+        let format = first.format;
+        let levels = self.subtextures[0].mipmaps.len();
+        let faces = self.subtextures.len();
+        if levels > 32 {
+            return Err(Error::Unrepresentable(format!("{} mipmap levels", levels)));
+        }
+        if self.subtextures.iter().any(|x| x.mipmaps.len() != levels) {
+            return Err(Error::Unrepresentable(
+                "subtextures with different numbers of mipmaps".into(),
+            ));
+        }
+        if let Some(mip) = self.mipmaps().find(|x| x.format != format) {
+            return Err(Error::Unrepresentable(format!(
+                "a {:?} mipmap in a {:?} texture",
+                mip.format, format
+            )));
+        }
+        let (layer_count, face_count) = match (self.kind, faces) {
+            (TextureKind::Cube, 6) => (0, 6),
+            (TextureKind::Cube, n) => {
+                return Err(Error::Unrepresentable(format!(
+                    "a cubemap with {} faces",
+                    n
+                )))
+            }
+            (TextureKind::TwoD, 1) => (0, 1),
+            (_, n) => (n as u32, 1),
+        };
+        debug!(?format, ?self.kind, levels, layer_count, face_count);
+
+        let dfd = format.ktx_dfd();
+        let swizzle = format.ktx_swizzle();
+        let kvd_orientation = match orientation {
+            Orientation::TopDown => "rd",
+            Orientation::BottomUp => "ru",
+        };
+        let mut pairs = vec![("KTXorientation", kvd_orientation)];
+        pairs.extend(swizzle.map(|x| ("KTXswizzle", x)));
+        let kvd = ktx_kvd(&pairs);
+        let dfd_offset = HEADER_LEN + LEVEL_INDEX_LEN * levels;
+        let kvd_offset = dfd_offset + dfd.len();
+
+        // every image is padded to the size its dimensions call for
+        let level_len = |level: usize| {
+            let width = (first.width >> level).max(1);
+            let height = (first.height >> level).max(1);
+            format.data_len(width, height)
+        };
+        let align = lcm(format.bytes_per_block(), 4);
+        let mut offsets = vec![0; levels];
+        let mut end = kvd_offset + kvd.len();
+        for level in (0..levels).rev() {
+            offsets[level] = end.next_multiple_of(align);
+            end = offsets[level] + level_len(level) * faces;
+        }
+
+        w.extend_from_slice(&IDENTIFIER);
+        let type_size = match format {
+            TextureFormat::RGB5 | TextureFormat::RGB5A1 | TextureFormat::RGBA4 => 2,
+            _ => 1,
+        };
+        for x in [
+            format.to_vk_format(),
+            type_size,
+            first.width,
+            first.height,
+            0,
+            layer_count,
+            face_count,
+            levels as u32,
+            0,
+            dfd_offset as u32,
+            dfd.len() as u32,
+            if kvd.is_empty() { 0 } else { kvd_offset as u32 },
+            kvd.len() as u32,
+        ] {
+            w.extend_from_slice(&x.to_le_bytes());
+        }
+        w.extend_from_slice(&[0; 16]);
+        for (level, offset) in offsets.iter().enumerate() {
+            let len = (level_len(level) * faces) as u64;
+            w.extend_from_slice(&(*offset as u64).to_le_bytes());
+            w.extend_from_slice(&len.to_le_bytes());
+            w.extend_from_slice(&len.to_le_bytes());
+        }
+        w.extend_from_slice(&dfd);
+        w.extend_from_slice(&kvd);
+
+        let mut pos = kvd_offset + kvd.len();
+        for level in (0..levels).rev() {
+            w.resize(w.len() + offsets[level] - pos, 0);
+            let len = level_len(level);
+            for subtex in &self.subtextures {
+                write_padded(w, &subtex.mipmaps[level], len)?;
+            }
+            pos = offsets[level] + len * faces;
+        }
+        debug!(len = pos);
+        Ok(())
+    }
+}
+
+/// Writes exactly `len` bytes of the data of `mip`, padded with zeroes
+fn write_padded(w: &mut Vec<u8>, mip: &Mipmap, len: usize) -> Result<(), Error> {
+    if mip.data.len() > len {
+        return Err(Error::Unrepresentable(format!(
+            "{} with {} bytes of data instead of {}",
+            mip,
+            mip.data.len(),
+            len
+        )));
+    }
+    w.extend_from_slice(&mip.data);
+    w.resize(w.len() + len - mip.data.len(), 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+
+    /// Re-reads `tex` with the ktx2 crate and checks every level against the mipmaps
+    fn check(tex: &Texture, layers: u32, faces: u32) {
+        let bytes = tex.to_ktx2(Orientation::BottomUp).unwrap();
+        let reader = ktx2::Reader::new(&bytes[..]).unwrap();
+        let header = reader.header();
+        let first = &tex.subtextures[0].mipmaps[0];
+        assert_eq!(
+            header.format.map(|x| x.0.get()),
+            Some(first.format.to_vk_format())
+        );
+        assert_eq!(
+            (header.pixel_width, header.pixel_height),
+            (first.width, first.height)
+        );
+        assert_eq!((header.layer_count, header.face_count), (layers, faces));
+        assert_eq!(
+            header.level_count as usize,
+            tex.subtextures[0].mipmaps.len()
+        );
+        assert!(header.supercompression_scheme.is_none());
+
+        for (level, data) in reader.levels().enumerate() {
+            let len = data.len() / tex.subtextures.len();
+            for (subtex, image) in tex.subtextures.iter().zip(data.chunks_exact(len)) {
+                let mip = &subtex.mipmaps[level];
+                let n = mip.data.len().min(len);
+                assert_eq!(image[..n], mip.data[..n], "level {}", level);
+                assert!(image[n..].iter().all(|&x| x == 0));
+            }
+        }
+
+        let dfd = reader.data_format_descriptors().next().unwrap();
+        let basic = ktx2::BasicDataFormatDescriptor::parse(dfd.data).unwrap();
+        let (bw, bh) = first.format.block_dims();
+        assert_eq!(basic.texel_block_dimensions[..2], [bw, bh]);
+        assert_eq!(
+            basic.bytes_planes[0] as usize,
+            first.format.bytes_per_block()
+        );
+        let (_, samples) = first.format.ktx_samples();
+        assert_eq!(basic.sample_information().count(), samples.len());
+    }
+
+    #[test]
+    fn export_game_textures() {
+        let atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        for tex in &atlas.textures {
+            check(tex, 0, 1);
+        }
+    }
+
+    #[test]
+    fn export_arrays_and_cubemaps() {
+        let atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        let face = atlas.textures[1].subtextures[0].clone();
+        check(&Texture::new(vec![face.clone(); 3]), 3, 1);
+        check(&Texture::new(vec![face.clone(); 6]), 6, 1);
+        check(&Texture::new_cube([(); 6].map(|_| face.clone())), 0, 6);
+    }
+
+    #[test]
+    fn swizzle() {
+        let mip = Mipmap {
+            width: 4,
+            height: 4,
+            format: TextureFormat::L8A8,
+            data: vec![7; 32].into(),
+            ..Default::default()
+        };
+        let tex = Texture::new(vec![Subtexture { mipmaps: vec![mip] }]);
+        check(&tex, 0, 1);
+        let bytes = tex.to_ktx2(Orientation::default()).unwrap();
+        let kvd = b"KTXswizzle\0rrrg\0";
+        assert!(bytes.windows(kvd.len()).any(|x| x == kvd));
+        assert_eq!(
+            Texture::new(vec![]).to_ktx2(Orientation::default()),
+            Err(Error::Unrepresentable("a texture without mipmaps".into()))
+        );
+        let mut tex = tex;
+        tex.subtextures[0].mipmaps[0].data.to_mut().push(7);
+        assert_eq!(
+            tex.to_ktx2(Orientation::BottomUp),
+            Err(Error::Unrepresentable(
+                "SubTex 4x4 L8A8 with 33 bytes of data instead of 32".into()
+            ))
+        );
+    }
+
+    /// Checks that `tex` survives a round trip, up to the padding of the smallest mipmaps
+    fn check_import(tex: &Texture) {
+        let bytes = tex.to_ktx2(Orientation::TopDown).unwrap();
+        let read = Texture::from_ktx2(&bytes, None).unwrap();
+        assert_eq!(read.subtextures.len(), tex.subtextures.len());
+        assert_eq!(read.kind, tex.kind);
+        for (a, b) in read.mipmaps().zip(tex.mipmaps()) {
+            assert_eq!(a.format, b.format);
+            assert_eq!((a.width, a.height), (b.width.max(1), b.height.max(1)));
+            assert_eq!(a.data[..b.data.len()], b.data[..]);
+        }
+        assert_eq!(read.mipmaps().count(), tex.mipmaps().count());
+    }
+
+    #[test]
+    fn import() {
+        let atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        for tex in &atlas.textures {
+            check_import(tex);
+        }
+        let face = atlas.textures[1].subtextures[0].clone();
+        check_import(&Texture::new(vec![face.clone(); 3]));
+        check_import(&Texture::new_cube([(); 6].map(|_| face.clone())));
+
+        let mip = Mipmap {
+            width: 4,
+            height: 2,
+            format: TextureFormat::A8,
+            data: (0..8).collect::<Vec<u8>>().into(),
+            ..Default::default()
+        };
+        let tex = Texture::new(vec![Subtexture { mipmaps: vec![mip] }]);
+        for (orientation, kvd, data) in [
+            (
+                Orientation::TopDown,
+                b"KTXorientation\0rd\0",
+                [4, 5, 6, 7, 0, 1, 2, 3],
+            ),
+            (
+                Orientation::BottomUp,
+                b"KTXorientation\0ru\0",
+                [0, 1, 2, 3, 4, 5, 6, 7],
+            ),
+        ] {
+            let bytes = tex.to_ktx2(orientation).unwrap();
+            assert!(bytes.windows(kvd.len()).any(|x| x == kvd));
+            assert_eq!(bytes[bytes.len() - 8..], data);
+            assert_eq!(Texture::from_ktx2(&bytes, None).unwrap(), tex);
+            let read = Texture::from_ktx2(&bytes, Some(Orientation::BottomUp)).unwrap();
+            assert_eq!(read.mipmaps().next().unwrap().data[..], data);
+        }
+
+        let mip = Mipmap {
+            width: 4,
+            height: 4,
+            format: TextureFormat::BC7,
+            data: (0..16).collect::<Vec<u8>>().into(),
+            ..Default::default()
+        };
+        let tex = Texture::new(vec![Subtexture { mipmaps: vec![mip] }]);
+        let bytes = tex.to_ktx2(Orientation::TopDown).unwrap();
+        let kvd = b"KTXorientation\0ru\0";
+        assert!(bytes.windows(kvd.len()).any(|x| x == kvd));
+        assert_eq!(Texture::from_ktx2(&bytes, None).unwrap(), tex);
+        let mut top_down = bytes.clone();
+        let pos = top_down.windows(kvd.len()).position(|x| x == kvd).unwrap();
+        top_down[pos + kvd.len() - 2] = b'd';
+        assert_eq!(Texture::from_ktx2(&top_down, None).unwrap(), tex);
+        assert!(matches!(
+            Texture::from_ktx2(&top_down, Some(Orientation::TopDown)),
+            Err(Error::Unflippable(_))
+        ));
+    }
+
+    #[test]
+    fn import_errors() {
+        assert!(matches!(
+            Texture::from_ktx2(MIKITM, None),
+            Err(Error::BadMagic {
+                found: [b'T', b'X', b'P', _]
+            })
+        ));
+        let atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        let bytes = atlas.textures[0].to_ktx2(Orientation::default()).unwrap();
+
+        let mut bad = bytes.clone();
+        bad[12..16].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::Unrepresentable("VkFormat 1000".into()))
+        );
+        let mut bad = bytes.clone();
+        bad[44..48].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::Unrepresentable("supercompression scheme 2".into()))
+        );
+        assert!(matches!(
+            Texture::from_ktx2(&bytes[..bytes.len() - 1], None),
+            Err(Error::TruncatedData { .. })
+        ));
+
+        // counts and sizes are checked before anything is allocated from them
+        let mut bad = bytes.clone();
+        bad[36..40].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::Unrepresentable("0 cubemap faces".into()))
+        );
+        let mut bad = bytes.clone();
+        bad[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::TruncatedData { .. })
+        ));
+        let mut bad = bytes.clone();
+        bad[20..28].fill(0xFF);
+        assert!(matches!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::TruncatedData { .. })
+        ));
+        let mut bad = bytes.clone();
+        bad[40..44].copy_from_slice(&40u32.to_le_bytes());
+        assert_eq!(
+            Texture::from_ktx2(&bad, None),
+            Err(Error::CountOverflow(40))
+        );
+
+        let mip = atlas.textures[0].subtextures[0].mipmaps[0].clone();
+        let tex = Texture::new(vec![Subtexture {
+            mipmaps: vec![mip; 40],
+        }]);
+        assert_eq!(
+            tex.to_ktx2(Orientation::default()),
+            Err(Error::Unrepresentable("40 mipmap levels".into()))
+        );
+    }
+}
@@ -5,21 +5,133 @@ use pyo3::prelude::*;
 
 #[cfg(feature = "ddsfile")]
 mod dds;
+mod decode;
+#[cfg(feature = "image")]
+mod encode;
+mod error;
+#[cfg(feature = "flate2")]
+mod farc;
+mod flip;
 #[cfg(feature = "image")]
 mod image;
 mod r#impl;
+mod ktx;
+mod obj;
+#[cfg(feature = "image")]
+mod pack;
 #[cfg(feature = "pyo3")]
 pub mod py_ffi;
 mod read;
-#[cfg(feature = "dcv-color-primitives")]
+#[cfg(feature = "image")]
+mod resample;
+mod section;
+mod spr;
+mod tex_db;
+mod validate;
+mod write;
 mod yuv;
 
+pub use error::Error;
+#[cfg(feature = "flate2")]
+pub use farc::{Farc, FarcEntry, FarcKind};
+pub use obj::ObjSet;
+#[cfg(feature = "image")]
+pub use pack::SpriteSetBuilder;
+pub use spr::{Rect, ResolutionMode, Sprite, SpriteSet};
+pub use tex_db::{TexDb, TexDbEntry};
+pub use validate::{Issue, MipLocation};
+
 #[derive(Debug, PartialEq, Clone)]
-pub struct TextureAtlas<'a>(pub Vec<Texture<'a>>);
+pub struct TextureAtlas<'a> {
+    pub textures: Vec<Texture<'a>>,
+    pub revision: Revision,
+    endianness: Endianness,
+    unk: u32,
+    /// The `MTXD` header of a [`Revision::Modern`] atlas, its flags and depth are written back
+    section: Option<section::Section>,
+}
+
+/// The layout an atlas was stored in
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Revision {
+    /// Used up to Project DIVA F, the atlas is stored as is
+    #[default]
+    Classic,
+    /// Used by Project DIVA F2nd and X/XHD, the atlas is wrapped in a `MTXD` section
+    Modern,
+}
+
+/// Byte order of a structure, files from the PS3 games are big endian
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+/// How much effort the block compression encoders spend on each block
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Quality {
+    /// Endpoints are taken from the bounding box of the block
+    Fast,
+    /// Endpoints are fitted along the principal axis of the block
+    #[default]
+    Normal,
+    /// Endpoints are further refined by least squares, and more candidates are tried
+    Best,
+}
+
+/// How the levels of a mipmap chain are downsampled from the base level
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct MipFilter {
+    pub kernel: FilterKernel,
+    /// Downsample in linear light instead of on the sRGB values
+    pub gamma_correct: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum FilterKernel {
+    /// Averages the pixels covered by each output pixel
+    #[default]
+    Box,
+    Triangle,
+    Lanczos3,
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Texture<'a> {
     pub subtextures: Vec<Subtexture<'a>>,
+    pub kind: TextureKind,
+    /// Id the objects refer to the texture by, not stored in the atlas
+    pub id: Option<u32>,
+    /// Name from the texture database, not stored in the atlas
+    pub name: Option<String>,
+    endianness: Endianness,
+    unk: u32,
+}
+
+/// How the subtextures of a [`Texture`] are laid out
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum TextureKind {
+    /// A single image, stored as `TXP\x04`
+    #[default]
+    TwoD,
+    /// Six faces in the order of [`Face`], stored as `TXP\x05` with a depth of 6
+    Cube,
+    /// Layers of the same size, stored as `TXP\x05`. Arrays of 6 layers cannot be written, the
+    /// games read them as cubemaps
+    Array,
+}
+
+/// A face of a cubemap, in the order the games, DDS and KTX2 store them
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Face {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -30,6 +142,7 @@ pub struct Subtexture<'a> {
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct Mipmap<'a> {
     id: u32,
+    endianness: Endianness,
     pub width: u32,
     pub height: u32,
     pub format: TextureFormat,
@@ -37,7 +150,7 @@ pub struct Mipmap<'a> {
 }
 
 #[non_exhaustive]
-#[derive(Debug, Default, PartialEq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(feature = "pyo3", pyclass)]
 pub enum TextureFormat {
     A8 = 0,
@@ -60,3 +173,28 @@ pub enum TextureFormat {
     /// Only available on MM+
     BC6H = 127,
 }
+
+/// The order of the rows of images, DDS and KTX2 files passed to or returned by the library
+///
+/// The games store textures bottom-up, while almost everything else expects them top-down.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "pyo3", pyclass)]
+pub enum Orientation {
+    /// The first row is the top of the image, the rows are flipped from the way they are stored
+    #[default]
+    TopDown,
+    /// The first row is the bottom of the image, the rows are kept the way they are stored
+    BottomUp,
+}
+
+/// The pixels [`Mipmap::decode_into`] writes, rows are tightly packed
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "pyo3", pyclass)]
+pub enum PixelLayout {
+    #[default]
+    Rgba8,
+    /// What Direct3D and most window systems expect
+    Bgra8,
+    /// Native endian `f32`s, BC6H keeps values above 1
+    Rgba32F,
+}
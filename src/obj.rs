@@ -0,0 +1,113 @@
+//! The texture id table of object sets (`obj_*.bin`), the companions of `_tex.bin` atlases.
+//!
+//! The `n`th texture of the atlas has the `n`th id of the table, see [`TextureAtlas::set_ids`].
+//! Only the classic layout is read, the objects themselves are skipped.
+use std::convert::TryInto;
+
+use nom::multi::count;
+use nom::number::complete::u32;
+use tracing::debug;
+
+use super::read::{take_exact, IResult};
+use super::*;
+
+/// Upper bytes of the signature, the low byte varies between games
+const SIGNATURE: u32 = 0x0506_2500;
+const SIGNATURE_MASK: u32 = 0xFFFF_FF00;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ObjSet {
+    /// Ids of the textures of the companion atlas, in order
+    pub texture_ids: Vec<u32>,
+}
+
+impl ObjSet {
+    /// Reads the texture ids of an object set, reporting why the file could not be read
+    pub fn from_bytes(input: &[u8]) -> Result<Self, Error> {
+        let (_, set) = Self::parse(input)?;
+        Ok(set)
+    }
+
+    #[tracing::instrument(name = "obj_set", skip(i0))]
+    pub fn parse(i0: &[u8]) -> IResult<&[u8], ObjSet> {
+        let (_, magic) = take_exact(4)(i0)?;
+        let magic: [u8; 4] = magic.try_into().unwrap();
+        let endian = if u32::from_le_bytes(magic) & SIGNATURE_MASK == SIGNATURE {
+            nom::number::Endianness::Little
+        } else if u32::from_be_bytes(magic) & SIGNATURE_MASK == SIGNATURE {
+            nom::number::Endianness::Big
+        } else {
+            return Err(nom::Err::Error(Error::BadMagic { found: magic }));
+        };
+        // signature, object count, bone count, then the offsets of the objects, skins,
+        // object names and object ids
+        let (i, _) = take_exact(0x1C)(i0)?;
+        let (i, ids_offset) = u32(endian)(i)?;
+        let (i, cnt) = u32(endian)(i)?;
+        debug!(?endian, ids_offset, cnt);
+
+        let table =
+            i0.get(ids_offset as usize..)
+                .ok_or(nom::Err::Failure(Error::OffsetOutOfBounds {
+                    offset: ids_offset as usize,
+                    len: i0.len(),
+                }))?;
+        if (cnt as usize).saturating_mul(4) > table.len() {
+            return Err(nom::Err::Failure(Error::CountOverflow(cnt)));
+        }
+        let (_, texture_ids) = count(u32(endian), cnt as usize)(table)?;
+        Ok((i, Self { texture_ids }))
+    }
+}
+
+impl<'a> TextureAtlas<'a> {
+    /// The texture with this id, once the ids are set with [`TextureAtlas::set_ids`]
+    pub fn by_id(&self, id: u32) -> Option<&Texture<'a>> {
+        self.textures.iter().find(|x| x.id == Some(id))
+    }
+
+    pub fn by_id_mut(&mut self, id: u32) -> Option<&mut Texture<'a>> {
+        self.textures.iter_mut().find(|x| x.id == Some(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+
+    /// The header of an object set without objects, followed by its texture ids
+    fn obj_set(ids: &[u32], to_bytes: fn(u32) -> [u8; 4]) -> Vec<u8> {
+        let mut header = [SIGNATURE, 0, 0, 0x28, 0, 0, 0, 0x28, ids.len() as u32, 0].to_vec();
+        header.extend(ids);
+        header.into_iter().flat_map(to_bytes).collect()
+    }
+
+    #[test]
+    fn texture_ids() {
+        let ids = [0xDEADBEEF, 0x1234, 0x5678];
+        let le = ObjSet::from_bytes(&obj_set(&ids, u32::to_le_bytes)).unwrap();
+        let be = ObjSet::from_bytes(&obj_set(&ids, u32::to_be_bytes)).unwrap();
+        assert_eq!(le.texture_ids, ids);
+        assert_eq!(be, le);
+
+        let mut atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        atlas.set_ids(le.texture_ids);
+        let tex = atlas.by_id(0xDEADBEEF).unwrap();
+        assert!(tex == &atlas.textures[0]);
+        assert!(atlas.by_id(0x5678).unwrap() == &atlas.textures[2]);
+        assert!(atlas.by_id(0x9999).is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            ObjSet::from_bytes(MIKITM),
+            Err(Error::BadMagic { .. })
+        ));
+        let mut bytes = obj_set(&[1, 2], u32::to_le_bytes);
+        bytes.truncate(bytes.len() - 4);
+        assert_eq!(ObjSet::from_bytes(&bytes), Err(Error::CountOverflow(2)));
+    }
+}
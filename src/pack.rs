@@ -0,0 +1,238 @@
+//! Packing loose images into the pages of a new sprite set, see [`SpriteSetBuilder`].
+use ::image::{imageops, DynamicImage, GenericImageView, RgbaImage};
+
+use super::*;
+
+/// Packs images into power-of-two pages and builds a [`SpriteSet`] from them.
+///
+/// Images are placed on shelves, tallest first, and every page is shrunk to the smallest
+/// power of two holding its sprites.
+#[derive(Debug, Clone)]
+pub struct SpriteSetBuilder {
+    sprites: Vec<(String, DynamicImage)>,
+    padding: u32,
+    max_page_size: u32,
+    quality: Quality,
+}
+
+impl Default for SpriteSetBuilder {
+    fn default() -> Self {
+        Self {
+            sprites: vec![],
+            padding: 2,
+            max_page_size: 2048,
+            quality: Quality::default(),
+        }
+    }
+}
+
+/// A row of sprites of a page
+struct Shelf {
+    y: u32,
+    height: u32,
+    width: u32,
+}
+
+#[derive(Default)]
+struct Page {
+    shelves: Vec<Shelf>,
+    width: u32,
+    height: u32,
+}
+
+impl Page {
+    /// Finds room for a `width`x`height` box, opening a new shelf if needed
+    fn place(&mut self, width: u32, height: u32, size: u32) -> Option<(u32, u32)> {
+        let fits = |x: &&mut Shelf| x.width + width <= size && height <= x.height;
+        let (x, y) = match self.shelves.iter_mut().find(fits) {
+            Some(shelf) => {
+                shelf.width += width;
+                (shelf.width - width, shelf.y)
+            }
+            None => {
+                let y = self.shelves.last().map_or(0, |x| x.y + x.height);
+                if y + height > size || width > size {
+                    return None;
+                }
+                self.shelves.push(Shelf { y, height, width });
+                (0, y)
+            }
+        };
+        self.width = self.width.max(x + width);
+        self.height = self.height.max(y + height);
+        Some((x, y))
+    }
+}
+
+impl SpriteSetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a sprite, the sprites of the built set keep the order they were added in
+    pub fn add(&mut self, name: impl Into<String>, image: DynamicImage) -> &mut Self {
+        self.sprites.push((name.into(), image));
+        self
+    }
+
+    /// Transparent pixels left around every sprite, 2 by default
+    pub fn padding(&mut self, padding: u32) -> &mut Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Largest side of a page, 2048 by default. Rounded down to a power of two
+    pub fn max_page_size(&mut self, size: u32) -> &mut Self {
+        self.max_page_size = size;
+        self
+    }
+
+    /// Quality of the block compression of the pages
+    pub fn quality(&mut self, quality: Quality) -> &mut Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Packs the sprites and encodes every page into `format`
+    pub fn build(&self, format: TextureFormat) -> Result<SpriteSet<'static>, Error> {
+        let size = match self.max_page_size {
+            0..=4 => 4,
+            size => 1 << size.ilog2(),
+        };
+        let mut order: Vec<usize> = (0..self.sprites.len()).collect();
+        order.sort_by_key(|&i| {
+            let (w, h) = self.sprites[i].1.dimensions();
+            (std::cmp::Reverse(h), std::cmp::Reverse(w))
+        });
+
+        let mut pages: Vec<Page> = vec![];
+        let mut placements = vec![(0, 0, 0); self.sprites.len()];
+        for i in order {
+            let (name, image) = &self.sprites[i];
+            let (w, h) = image.dimensions();
+            let (w, h) = (w + 2 * self.padding, h + 2 * self.padding);
+            let placed = pages
+                .iter_mut()
+                .enumerate()
+                .find_map(|(n, page)| Some((n, page.place(w, h, size)?)));
+            let (n, (x, y)) = match placed {
+                Some(placed) => placed,
+                None => {
+                    let mut page = Page::default();
+                    let at = page.place(w, h, size).ok_or_else(|| {
+                        Error::Unrepresentable(format!(
+                            "sprite `{}` of {}x{} in pages of {}x{}",
+                            name,
+                            image.width(),
+                            image.height(),
+                            size,
+                            size
+                        ))
+                    })?;
+                    pages.push(page);
+                    (pages.len() - 1, at)
+                }
+            };
+            placements[i] = (n, x + self.padding, y + self.padding);
+        }
+
+        let sizes: Vec<(u32, u32)> = pages
+            .iter()
+            .map(|x| {
+                (
+                    x.width.next_power_of_two().max(4),
+                    x.height.next_power_of_two().max(4),
+                )
+            })
+            .collect();
+        let mut canvases: Vec<RgbaImage> =
+            sizes.iter().map(|&(w, h)| RgbaImage::new(w, h)).collect();
+        let mut sprites = Vec::with_capacity(self.sprites.len());
+        for ((name, image), &(n, x, y)) in self.sprites.iter().zip(&placements) {
+            imageops::replace(&mut canvases[n], &image.to_rgba8(), x.into(), y.into());
+            let rect = Rect {
+                x: x as f32,
+                y: y as f32,
+                width: image.width() as f32,
+                height: image.height() as f32,
+            };
+            sprites.push(Sprite::new(name.clone(), n as u32, rect, sizes[n]));
+        }
+
+        let textures = canvases
+            .into_iter()
+            .map(|canvas| {
+                let page = DynamicImage::ImageRgba8(canvas);
+                let mip = Mipmap::from_image(&page, format, self.quality, Orientation::TopDown)?;
+                Ok(Texture::new(vec![Subtexture { mipmaps: vec![mip] }]))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let names = (0..textures.len())
+            .map(|i| format!("MERGE_COMP_{}", i))
+            .collect();
+        Ok(SpriteSet::new(TextureAtlas::new(textures), names, sprites))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, i: u8) -> DynamicImage {
+        let px = ::image::Rgba([i * 40, 255 - i * 40, i, 255]);
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, px))
+    }
+
+    #[test]
+    fn pack_pages() {
+        let sizes = [(20, 10), (30, 30), (60, 12), (5, 40), (16, 16)];
+        let mut builder = SpriteSetBuilder::new();
+        builder.max_page_size(64).padding(1);
+        for (i, &(w, h)) in sizes.iter().enumerate() {
+            builder.add(format!("SPR_{}", i), solid(w, h, i as u8));
+        }
+        let set = builder.build(TextureFormat::RGBA8).unwrap();
+        assert!(set.atlas.textures.len() > 1);
+        assert_eq!(set.texture_names.len(), set.atlas.textures.len());
+
+        for (i, sprite) in set.sprites.iter().enumerate() {
+            assert_eq!(sprite.name, format!("SPR_{}", i));
+            let (w, h) = sizes[i];
+            assert_eq!(
+                (sprite.rect.width, sprite.rect.height),
+                (w as f32, h as f32)
+            );
+            let image = set.sprite_image(&sprite.name).unwrap();
+            assert_eq!(image.to_rgba8(), solid(w, h, i as u8).to_rgba8());
+            for other in &set.sprites[..i] {
+                let (a, b) = (sprite.rect, other.rect);
+                let disjoint = a.x + a.width < b.x
+                    || b.x + b.width < a.x
+                    || a.y + a.height < b.y
+                    || b.y + b.height < a.y;
+                assert!(other.texture_index != sprite.texture_index || disjoint);
+            }
+        }
+        for tex in &set.atlas.textures {
+            let mip = &tex.subtextures[0].mipmaps[0];
+            assert!(mip.width.is_power_of_two() && mip.width <= 64);
+            assert!(mip.height.is_power_of_two() && mip.height <= 64);
+        }
+        let bytes = set.to_bytes().unwrap();
+        assert_eq!(SpriteSet::from_bytes(&bytes).unwrap(), set);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut builder = SpriteSetBuilder::new();
+        builder.max_page_size(64).add("BIG", solid(63, 8, 0));
+        assert!(matches!(
+            builder.build(TextureFormat::DXT5),
+            Err(Error::Unrepresentable(_))
+        ));
+        builder.padding(0);
+        let set = builder.build(TextureFormat::DXT5).unwrap();
+        let mip = &set.atlas.textures[0].subtextures[0].mipmaps[0];
+        assert_eq!((mip.width, mip.height), (64, 8));
+    }
+}
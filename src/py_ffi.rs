@@ -1,3 +1,8 @@
+#[cfg(feature = "image")]
+use std::convert::TryFrom;
+
+#[cfg(feature = "image")]
+use ::image::DynamicImage;
 use pyo3::exceptions::PyException;
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
@@ -16,6 +21,13 @@ pub struct PyTextureAtlas {
 pub struct PyTexture {
     #[pyo3(get, set)]
     pub subtextures: Vec<PySubtexture>,
+    #[pyo3(get, set)]
+    pub id: Option<u32>,
+    #[pyo3(get, set)]
+    pub name: Option<String>,
+    kind: TextureKind,
+    endianness: Endianness,
+    unk: u32,
 }
 
 #[pyclass]
@@ -28,7 +40,9 @@ pub struct PySubtexture {
 #[pyclass]
 #[derive(Debug, PartialEq, Clone)]
 pub struct PyMipmap {
+    #[pyo3(get)]
     id: u32,
+    endianness: Endianness,
     #[pyo3(get, set)]
     pub width: u32,
     #[pyo3(get, set)]
@@ -44,8 +58,8 @@ impl PyMipmap {
     #[cfg(feature = "image")]
     fn to_rgb(&self) -> Option<Vec<(u8, u8, u8)>> {
         let sub: Mipmap<'_> = self.clone().into();
-        sub.to_dynamic_image().map(|x| {
-            x.to_rgb()
+        DynamicImage::try_from(&sub).ok().map(|x| {
+            x.to_rgb8()
                 .pixels()
                 .map(|x| (x.0[0], x.0[1], x.0[2]))
                 .collect()
@@ -54,14 +68,28 @@ impl PyMipmap {
     #[cfg(feature = "image")]
     fn to_rgba(&self) -> Option<Vec<(u8, u8, u8, u8)>> {
         let sub: Mipmap<'_> = self.clone().into();
-        sub.to_dynamic_image().map(|x| {
-            x.to_rgba()
+        DynamicImage::try_from(&sub).ok().map(|x| {
+            x.to_rgba8()
                 .pixels()
                 .map(|x| (x.0[0], x.0[1], x.0[2], x.0[3]))
                 .collect()
         })
     }
 
+    /// Decodes the mipmap into top-down RGBA8 bytes
+    fn decode_rgba8(&self) -> PyResult<Vec<u8>> {
+        let mip: Mipmap<'_> = self.clone().into();
+        Ok(mip.decode_rgba8().map_err(ExternalError)?)
+    }
+    /// Decodes the mipmap into the bytes of `layout`, with the rows in `orientation`
+    fn decode(&self, layout: PixelLayout, orientation: Orientation) -> PyResult<Vec<u8>> {
+        let mip: Mipmap<'_> = self.clone().into();
+        let mut out = vec![0; mip.width as usize * mip.height as usize * layout.bytes_per_pixel()];
+        mip.decode_into(&mut out, layout, orientation)
+            .map_err(ExternalError)?;
+        Ok(out)
+    }
+
     fn __repr__(&self) -> PyResult<String> {
         Ok(format!(
             "PyMipMap: {:?} {}x{} ({} bytes)",
@@ -75,7 +103,7 @@ impl PyMipmap {
 
 impl<'a> From<TextureAtlas<'a>> for PyTextureAtlas {
     fn from(atlas: TextureAtlas<'a>) -> Self {
-        let textures = atlas.0.into_iter().map(Into::into).collect();
+        let textures = atlas.textures.into_iter().map(Into::into).collect();
         Self { textures }
     }
 }
@@ -83,13 +111,27 @@ impl<'a> From<TextureAtlas<'a>> for PyTextureAtlas {
 impl<'a> From<Texture<'a>> for PyTexture {
     fn from(tex: Texture<'a>) -> Self {
         let subtextures = tex.subtextures.into_iter().map(Into::into).collect();
-        Self { subtextures }
+        Self {
+            subtextures,
+            kind: tex.kind,
+            id: tex.id,
+            name: tex.name,
+            endianness: tex.endianness,
+            unk: tex.unk,
+        }
     }
 }
 impl<'a> From<PyTexture> for Texture<'a> {
     fn from(tex: PyTexture) -> Self {
         let subtextures = tex.subtextures.into_iter().map(Into::into).collect();
-        Self { subtextures }
+        Self {
+            subtextures,
+            kind: tex.kind,
+            id: tex.id,
+            name: tex.name,
+            endianness: tex.endianness,
+            unk: tex.unk,
+        }
     }
 }
 
@@ -110,6 +152,7 @@ impl<'a> From<Mipmap<'a>> for PyMipmap {
     fn from(sub: Mipmap<'a>) -> Self {
         let Mipmap {
             id,
+            endianness,
             width,
             height,
             format,
@@ -118,6 +161,7 @@ impl<'a> From<Mipmap<'a>> for PyMipmap {
         let data = data.into_owned();
         Self {
             id,
+            endianness,
             width,
             height,
             format,
@@ -129,6 +173,7 @@ impl<'a> From<PyMipmap> for Mipmap<'a> {
     fn from(mip: PyMipmap) -> Self {
         let PyMipmap {
             id,
+            endianness,
             width,
             height,
             format,
@@ -137,6 +182,7 @@ impl<'a> From<PyMipmap> for Mipmap<'a> {
         let data = data.into();
         Self {
             id,
+            endianness,
             width,
             height,
             format,
@@ -165,9 +211,9 @@ impl<E: std::error::Error> From<ExternalError<E>> for PyErr {
 
 #[pymethods]
 impl PyTexture {
-    fn to_dds_bytes(&self) -> PyResult<Vec<u8>> {
+    fn to_dds_bytes(&self, orientation: Option<Orientation>) -> PyResult<Vec<u8>> {
         let tex: Texture<'_> = self.clone().into();
-        let dds = tex.to_dds().map_err(ExternalError)?;
+        let dds = tex.to_dds(orientation).map_err(ExternalError)?;
         let mut vec = vec![];
         dds.write(&mut vec).map_err(ExternalError)?;
         Ok(vec)
@@ -208,7 +254,7 @@ fn read(path: String) -> PyResult<PyTextureAtlas> {
     let mut file = File::open(path)?;
     let mut input = vec![];
     file.read_to_end(&mut input)?;
-    let (_, txp) = TextureAtlas::parse(&input).unwrap();
+    let txp = TextureAtlas::from_bytes(&input).map_err(ExternalError)?;
     Ok(txp.into())
 }
 
@@ -221,6 +267,8 @@ fn txp(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyTexture>()?;
     m.add_class::<PyMipmap>()?;
     m.add_class::<TextureFormat>()?;
+    m.add_class::<PixelLayout>()?;
+    m.add_class::<Orientation>()?;
 
     Ok(())
 }
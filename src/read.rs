@@ -1,37 +1,103 @@
 use std::convert::TryInto;
 
 use nom::branch::alt;
-use nom::bytes::complete::tag;
-use nom::combinator::map;
-use nom::combinator::map_opt;
+use nom::bytes::complete::{take, take_till};
+use nom::combinator::cut;
 use nom::error::ParseError;
 use nom::multi::count;
-use nom::multi::length_data;
 use nom::number::complete::u32;
-use nom::IResult;
 use nom::Parser;
-use tracing::{debug, instrument, trace};
+use tracing::{debug, trace};
 
+use super::section::Section;
 use super::*;
 
+pub(crate) type IResult<I, O> = nom::IResult<I, O, Error>;
+
 fn parse_magic(id: u8) -> impl Fn(&[u8]) -> IResult<&[u8], nom::number::Endianness> {
     use nom::number::Endianness::*;
     move |i: &[u8]| {
-        let (i, res) = alt((tag(&[0x54, 0x58, 0x50, id]), tag(&[id, 0x50, 0x58, 0x54])))(i)?;
-        Ok((i, if res[3] == id { Little } else { Big }))
+        let (i, res) = take_exact(4)(i)?;
+        match res {
+            [0x54, 0x58, 0x50, x] if *x == id => Ok((i, Little)),
+            [x, 0x50, 0x58, 0x54] if *x == id => Ok((i, Big)),
+            _ => Err(nom::Err::Error(Error::BadMagic {
+                found: res.try_into().unwrap(),
+            })),
+        }
     }
 }
 
+/// Like `take`, but reports how much data was missing
+pub(crate) fn take_exact(len: usize) -> impl Fn(&[u8]) -> IResult<&[u8], &[u8]> {
+    move |i: &[u8]| {
+        if i.len() < len {
+            return Err(nom::Err::Failure(Error::TruncatedData {
+                expected: len,
+                available: i.len(),
+            }));
+        }
+        take(len)(i)
+    }
+}
+
+/// A NUL-terminated string, invalid UTF-8 is replaced
+pub(crate) fn parse_string(i: &[u8]) -> IResult<&[u8], String> {
+    let (i, s) = take_till(|x| x == 0)(i)?;
+    let (i, _) = take_exact(1)(i)?;
+    Ok((i, String::from_utf8_lossy(s).into_owned()))
+}
+
 impl<'a> TextureAtlas<'a> {
-    #[tracing::instrument(name = "atlas", skip(i0))]
+    /// Parses an atlas, reporting why the file could not be read
+    pub fn from_bytes(input: &'a [u8]) -> Result<Self, Error> {
+        let (_, atlas) = Self::parse(input)?;
+        Ok(atlas)
+    }
+
+    /// Parses an atlas of either [`Revision`]
     pub fn parse(i0: &'a [u8]) -> IResult<&'a [u8], TextureAtlas<'a>> {
+        alt((Self::parse_modern, Self::parse_classic))(i0)
+    }
+
+    #[tracing::instrument(name = "atlas", skip(i0))]
+    fn parse_classic(i0: &'a [u8]) -> IResult<&'a [u8], TextureAtlas<'a>> {
         let (i, endian) = parse_magic(3)(i0)?;
         let (i, map_count) = u32(endian)(i)?;
         let (i, unk) = u32(endian)(i)?;
         debug!(?endian, map_count, unk);
         let parse = alt((Texture::parse, Texture::parse_array));
-        let (_, maps) = offset_table(i0, parse, map_count.try_into().unwrap(), endian).parse(i)?;
-        Ok((i, Self(maps)))
+        let (_, textures) = offset_table(i0, parse, map_count, endian).parse(i)?;
+        Ok((
+            i,
+            Self {
+                textures,
+                revision: Revision::Classic,
+                endianness: endian.into(),
+                unk,
+                section: None,
+            },
+        ))
+    }
+
+    #[tracing::instrument(name = "mtxd", skip(i0))]
+    fn parse_modern(i0: &'a [u8]) -> IResult<&'a [u8], TextureAtlas<'a>> {
+        let (_, section) = Section::parse(*b"MTXD", i0)?;
+        debug!(endianness = ?section.endianness());
+        let (data, i) = section.data(i0).map_err(nom::Err::Failure)?;
+        let (_, atlas) = cut(Self::parse_classic)(data)?;
+        let i = match Section::parse(*b"EOFC", i) {
+            Ok((_, eofc)) => eofc.data(i).map_err(nom::Err::Failure)?.1,
+            Err(_) => i,
+        };
+        Ok((
+            i,
+            Self {
+                revision: Revision::Modern,
+                section: Some(section),
+                ..atlas
+            },
+        ))
     }
 }
 
@@ -42,31 +108,55 @@ impl<'a> Texture<'a> {
         let (i, mip_count) = u32(endian)(i)?;
         let (i, unk) = u32(endian)(i)?;
         debug!(?endian, mip_count, unk);
-        let (_, mipmaps) =
-            offset_table(i0, Mipmap::parse, mip_count.try_into().unwrap(), endian).parse(i)?;
+        let (_, mipmaps) = offset_table(i0, Mipmap::parse, mip_count, endian).parse(i)?;
         Ok((
             i,
             Self {
                 subtextures: vec![Subtexture { mipmaps }],
+                kind: TextureKind::TwoD,
+                id: None,
+                name: None,
+                endianness: endian.into(),
+                unk,
             },
         ))
     }
     #[tracing::instrument(name = "array", skip(i0))]
     pub fn parse_array(i0: &'a [u8]) -> IResult<&'a [u8], Texture<'a>> {
-        use nom::multi::count;
         let (i, endian) = parse_magic(5)(i0)?;
         let (i, total_mip_count) = u32(endian)(i)?;
         let (i, mipdata) = u32(endian)(i)?;
         let depth = (mipdata & 0xFF00) >> 8;
+        if depth == 0 || total_mip_count % depth != 0 {
+            return Err(nom::Err::Failure(Error::InvalidDepth {
+                mip_count: total_mip_count,
+                depth,
+            }));
+        }
         let mip_count = total_mip_count / depth;
         let (_, subtextures) = count(
-            offset_table(i0, Mipmap::parse, mip_count.try_into().unwrap(), endian)
+            offset_table(i0, Mipmap::parse, mip_count, endian)
                 .map(|mipmaps| Subtexture { mipmaps }),
             depth as usize,
         )
         .parse(i)?;
-        //let sides = sides.into_iter().map(|mipmaps| Side { mipmaps }).collect();
-        Ok((i, Self { subtextures }))
+        // the games only store cubemaps this way
+        let kind = if depth == 6 {
+            TextureKind::Cube
+        } else {
+            TextureKind::Array
+        };
+        Ok((
+            i,
+            Self {
+                subtextures,
+                kind,
+                id: None,
+                name: None,
+                endianness: endian.into(),
+                unk: mipdata,
+            },
+        ))
     }
 }
 
@@ -77,7 +167,6 @@ where
     F: Parser<I, O, E>,
     E: ParseError<I>,
 {
-    use nom::bytes::complete::*;
     move |i: I| {
         let (i0, _) = take(offset)(i.clone())?;
         let (_, v) = f.parse(i0)?;
@@ -85,23 +174,35 @@ where
     }
 }
 
-fn offset_table<'a, F, O, E>(
+/// Parses `cnt` offsets relative to `i0`, then runs `f` at each of them.
+///
+/// Once the table is read, any failure of `f` is final and is not backtracked by `alt`
+pub(crate) fn offset_table<'a, F, O>(
     i0: &'a [u8],
-    mut f: F,
-    cnt: usize,
+    f: F,
+    cnt: u32,
     endian: nom::number::Endianness,
-) -> impl Parser<&'a [u8], Vec<O>, E>
+) -> impl Parser<&'a [u8], Vec<O>, Error>
 where
-    F: Parser<&'a [u8], O, E>,
-    E: ParseError<&'a [u8]>,
+    F: Parser<&'a [u8], O, Error>,
 {
+    let mut f = cut(f);
     move |i: &'a [u8]| {
-        // let (i1, offset) = f1(i)?;
-        let f1 = u32(endian)(i)?;
-        let (i1, offsets) = count(u32(endian).map(|x| x as usize), cnt)(i)?;
+        let len: usize = cnt
+            .try_into()
+            .ok()
+            .filter(|&x: &usize| x.saturating_mul(4) <= i.len())
+            .ok_or(nom::Err::Failure(Error::CountOverflow(cnt)))?;
+        let (i1, offsets) = count(u32(endian).map(|x| x as usize), len)(i)?;
         let mut res = vec![];
         let mut f0 = |x: &'a [u8]| f.parse(x);
         for offset in offsets {
+            if offset >= i0.len() {
+                return Err(nom::Err::Failure(Error::OffsetOutOfBounds {
+                    offset,
+                    len: i0.len(),
+                }));
+            }
             let (_, val) = at_offset(offset, &mut f0).parse(i0)?;
             res.push(val);
         }
@@ -115,9 +216,12 @@ impl<'a> Mipmap<'a> {
         let (i, endian) = parse_magic(2)(i)?;
         let (i, width) = u32(endian)(i)?;
         let (i, height) = u32(endian)(i)?;
-        let (i, format) = map_opt(u32(endian), TextureFormat::from_id)(i)?;
+        let (i, format) = u32(endian)(i)?;
+        let format = TextureFormat::from_id(format)
+            .ok_or(nom::Err::Failure(Error::UnknownFormat(format)))?;
         let (i, id) = u32(endian)(i)?;
-        let (i, data) = length_data(u32(endian))(i)?;
+        let (i, len) = u32(endian)(i)?;
+        let (i, data) = take_exact(len as usize)(i)?;
         let data = data.into();
         trace!(width, height, ?format, id);
         Ok((
@@ -127,16 +231,25 @@ impl<'a> Mipmap<'a> {
                 height,
                 format,
                 id,
+                endianness: endian.into(),
                 data,
             },
         ))
     }
 }
 
+impl From<nom::number::Endianness> for Endianness {
+    fn from(endian: nom::number::Endianness) -> Self {
+        match endian {
+            nom::number::Endianness::Big => Self::Big,
+            _ => Self::Little,
+        }
+    }
+}
+
 impl TextureFormat {
-    #[tracing::instrument(error)]
+    #[tracing::instrument(level = "trace", ret)]
     pub(crate) fn from_id(id: u32) -> Option<Self> {
-        use super::TextureFormat::*;
         match id {
             0 => Some(Self::A8),
             1 => Some(Self::RGB8),
@@ -188,5 +301,27 @@ mod tests {
     #[test]
     fn read_atlas() {
         let (_, atlas) = TextureAtlas::parse(INPUT).unwrap();
+        assert_eq!(atlas.textures.len(), 18);
+        assert_eq!(atlas.revision, Revision::Classic);
+    }
+
+    #[test]
+    fn read_errors() {
+        let err = TextureAtlas::from_bytes(&INPUT[TEX_OFF..]).unwrap_err();
+        assert_eq!(err, Error::BadMagic { found: *b"TXP\x04" });
+
+        let mut input = INPUT.to_vec();
+        input[MIP_OFF + 12] = 14;
+        let err = TextureAtlas::from_bytes(&input).unwrap_err();
+        assert_eq!(err, Error::UnknownFormat(14));
+
+        let err = TextureAtlas::from_bytes(&INPUT[..INPUT.len() - 1]).unwrap_err();
+        assert_eq!(
+            err,
+            Error::TruncatedData {
+                expected: 2,
+                available: 1
+            }
+        );
     }
 }
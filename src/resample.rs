@@ -0,0 +1,211 @@
+//! Separable resampling of RGBA32F images, used to generate mipmaps.
+use super::*;
+
+/// Number of mipmaps the games use for a texture of this size.
+///
+/// The chain stops once the largest side reaches 2, e.g. a 256x256 texture has 8 levels
+pub(crate) fn level_count(width: u32, height: u32) -> u32 {
+    let largest = width.max(height);
+    if largest < 2 {
+        1
+    } else {
+        largest.ilog2()
+    }
+}
+
+impl FilterKernel {
+    fn radius(self) -> f32 {
+        match self {
+            Self::Box => 0.5,
+            Self::Triangle => 1.0,
+            Self::Lanczos3 => 3.0,
+        }
+    }
+
+    fn eval(self, x: f32) -> f32 {
+        match self {
+            Self::Box => {
+                if (-0.5..0.5).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Triangle => (1.0 - x.abs()).max(0.0),
+            Self::Lanczos3 => {
+                if x == 0.0 {
+                    1.0
+                } else if x.abs() < 3.0 {
+                    let x = x * std::f32::consts::PI;
+                    3.0 * x.sin() * (x / 3.0).sin() / (x * x)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// The first source pixel and the weights of the pixels contributing to each output pixel
+fn contributions(src: u32, dst: u32, kernel: FilterKernel) -> Vec<(usize, Vec<f32>)> {
+    let scale = src as f32 / dst as f32;
+    let stretch = scale.max(1.0);
+    let support = kernel.radius() * stretch;
+    (0..dst)
+        .map(|x| {
+            let center = (x as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| kernel.eval((j as f32 + 0.5 - center) / stretch))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Resizes tightly packed RGBA32F pixels, rows first then columns
+pub(crate) fn resize(
+    rgba: &[f32],
+    (width, height): (u32, u32),
+    (new_width, new_height): (u32, u32),
+    kernel: FilterKernel,
+) -> Vec<f32> {
+    let (w, nw, nh) = (width as usize, new_width as usize, new_height as usize);
+
+    let mut rows = vec![0.0; nw * height as usize * 4];
+    let horizontal = contributions(width, new_width, kernel);
+    for y in 0..height as usize {
+        for (x, (start, weights)) in horizontal.iter().enumerate() {
+            let out = &mut rows[(y * nw + x) * 4..][..4];
+            for (i, weight) in weights.iter().enumerate() {
+                let px = &rgba[(y * w + start + i) * 4..][..4];
+                for c in 0..4 {
+                    out[c] += px[c] * weight;
+                }
+            }
+        }
+    }
+
+    let mut out = vec![0.0; nw * nh * 4];
+    let vertical = contributions(height, new_height, kernel);
+    for (y, (start, weights)) in vertical.iter().enumerate() {
+        for x in 0..nw {
+            let px_out = &mut out[(y * nw + x) * 4..][..4];
+            for (i, weight) in weights.iter().enumerate() {
+                let px = &rows[((start + i) * nw + x) * 4..][..4];
+                for c in 0..4 {
+                    px_out[c] += px[c] * weight;
+                }
+            }
+        }
+    }
+    out
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Converts RGBA8 to premultiplied RGBA32F, in linear light if `gamma_correct`
+pub(crate) fn to_premultiplied(rgba: &[u8], gamma_correct: bool) -> Vec<f32> {
+    rgba.chunks_exact(4)
+        .flat_map(|px| {
+            let a = px[3] as f32 / 255.0;
+            let c = |x: u8| {
+                let x = x as f32 / 255.0;
+                if gamma_correct {
+                    srgb_to_linear(x) * a
+                } else {
+                    x * a
+                }
+            };
+            [c(px[0]), c(px[1]), c(px[2]), a]
+        })
+        .collect()
+}
+
+/// The inverse of [`to_premultiplied`]
+pub(crate) fn from_premultiplied(rgba: &[f32], gamma_correct: bool) -> Vec<u8> {
+    let quantize = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+    rgba.chunks_exact(4)
+        .flat_map(|px| {
+            let a = px[3].clamp(0.0, 1.0);
+            let c = |x: f32| {
+                let x = if a > 0.0 { x / a } else { 0.0 };
+                if gamma_correct {
+                    quantize(linear_to_srgb(x.clamp(0.0, 1.0)))
+                } else {
+                    quantize(x)
+                }
+            };
+            [c(px[0]), c(px[1]), c(px[2]), quantize(a)]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_level_counts() {
+        // sizes and counts from mikitm001_tex.txp
+        assert_eq!(level_count(256, 512), 9);
+        assert_eq!(level_count(64, 128), 7);
+        assert_eq!(level_count(256, 64), 8);
+        assert_eq!(level_count(512, 256), 9);
+        assert_eq!(level_count(8, 8), 3);
+        assert_eq!(level_count(1, 1), 1);
+    }
+
+    #[test]
+    fn box_averages() {
+        let rgba = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+        let rgba = [rgba, rgba].concat();
+        let out = resize(&rgba, (2, 2), (1, 1), FilterKernel::Box);
+        assert_eq!(out, [0.5, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn constant_stays_constant() {
+        let rgba = [0.25f32, 0.5, 0.75, 1.0].repeat(12 * 7);
+        for kernel in [
+            FilterKernel::Box,
+            FilterKernel::Triangle,
+            FilterKernel::Lanczos3,
+        ] {
+            let out = resize(&rgba, (12, 7), (5, 3), kernel);
+            assert_eq!(out.len(), 5 * 3 * 4);
+            for (a, b) in out.iter().zip(rgba.iter()) {
+                assert!((a - b).abs() < 1e-5, "{:?}", kernel);
+            }
+        }
+    }
+
+    #[test]
+    fn premultiplied_roundtrip() {
+        let rgba = [10, 128, 250, 255, 200, 100, 50, 128, 1, 2, 3, 0];
+        for gamma_correct in [false, true] {
+            let out = from_premultiplied(&to_premultiplied(&rgba, gamma_correct), gamma_correct);
+            assert_eq!(out[..8], rgba[..8]);
+            assert_eq!(out[11], 0);
+        }
+    }
+}
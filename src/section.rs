@@ -0,0 +1,119 @@
+//! Sections wrap the data of the formats introduced in Project DIVA F2nd and X/XHD.
+//!
+//! Every section starts with a `0x20` byte little endian header, followed by its data.
+//! A file is terminated by an empty `EOFC` section.
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+use nom::bytes::complete::take;
+use nom::number::complete::le_u32;
+use tracing::trace;
+
+use super::*;
+
+pub(crate) const HEADER_LEN: u32 = 0x20;
+const FLAGS: u32 = 0x1000_0000;
+const FLAG_BIG_ENDIAN: u32 = 0x0800_0000;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Section {
+    pub signature: [u8; 4],
+    /// Size of the data following the header
+    pub size: u32,
+    pub data_offset: u32,
+    pub flags: u32,
+    pub depth: u32,
+    pub data_size: u32,
+}
+
+impl Section {
+    pub fn new(signature: [u8; 4], data_size: u32, endianness: Endianness) -> Self {
+        let flags = match endianness {
+            Endianness::Little => FLAGS,
+            Endianness::Big => FLAGS | FLAG_BIG_ENDIAN,
+        };
+        Self {
+            signature,
+            size: data_size,
+            data_offset: HEADER_LEN,
+            flags,
+            depth: 0,
+            data_size,
+        }
+    }
+
+    /// Parses a section header with the given signature
+    #[tracing::instrument(name = "section", skip(i))]
+    pub fn parse(signature: [u8; 4], i: &[u8]) -> nom::IResult<&[u8], Self, Error> {
+        let (i, found) = take(4usize)(i)?;
+        if found != signature {
+            return Err(nom::Err::Error(Error::BadMagic {
+                found: found.try_into().unwrap(),
+            }));
+        }
+        let (i, size) = le_u32(i)?;
+        let (i, data_offset) = le_u32(i)?;
+        let (i, flags) = le_u32(i)?;
+        let (i, depth) = le_u32(i)?;
+        let (i, data_size) = le_u32(i)?;
+        let section = Self {
+            signature,
+            size,
+            data_offset,
+            flags,
+            depth,
+            data_size,
+        };
+        trace!(?section);
+        Ok((i, section))
+    }
+
+    /// Returns the data of the section that starts at `i0`, and the input following the section
+    pub fn data<'a>(&self, i0: &'a [u8]) -> Result<(&'a [u8], &'a [u8]), Error> {
+        let start = self.data_offset as usize;
+        let end = start + self.size as usize;
+        let section = i0.get(..end).ok_or(Error::TruncatedData {
+            expected: end,
+            available: i0.len(),
+        })?;
+        let data = &section[start..];
+        let data = data.get(..self.data_size as usize).unwrap_or(data);
+        Ok((data, &i0[end..]))
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.signature)?;
+        for x in [
+            self.size,
+            self.data_offset,
+            self.flags,
+            self.depth,
+            self.data_size,
+        ] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        w.write_all(&[0; 8])
+    }
+
+    /// Takes the flags and depth of `read`, the header this section replaces, but keeps the byte
+    /// order of `self`
+    pub fn with_header_of(self, read: &Section) -> Self {
+        Self {
+            flags: read.flags & !FLAG_BIG_ENDIAN | self.flags & FLAG_BIG_ENDIAN,
+            depth: read.depth,
+            ..self
+        }
+    }
+
+    pub fn eofc(endianness: Endianness) -> Self {
+        Self::new(*b"EOFC", 0, endianness)
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        if self.flags & FLAG_BIG_ENDIAN != 0 {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Sprite sets (`spr_*.bin`), an atlas with named rectangles cut out of its textures.
+//!
+//! The file starts with a `0x20` byte header of offsets, all relative to the start of the file:
+//! the atlas, the sprite table, the texture names, the sprite names and the sprite modes.
+//!
+//! No sprite set from the games is in the test assets, the layout is only checked against sets
+//! written by [`SpriteSet::write`] around the bare atlas of `spr_sel_pv616.bin`.
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+use nom::combinator::cut;
+use nom::multi::count;
+use nom::number::complete::{f32, u32};
+use nom::Parser;
+use tracing::debug;
+
+use super::read::{offset_table, parse_string, IResult};
+use super::write::{write_u32, write_vec};
+use super::*;
+
+const HEADER_LEN: usize = 0x20;
+const SPRITE_LEN: usize = 0x28;
+const MODE_LEN: usize = 8;
+const ATLAS_ALIGN: usize = 0x10;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpriteSet<'a> {
+    pub atlas: TextureAtlas<'a>,
+    /// Name of each texture of the atlas
+    pub texture_names: Vec<String>,
+    pub sprites: Vec<Sprite>,
+    unk: u32,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Sprite {
+    pub name: String,
+    /// Index of the texture in the atlas
+    pub texture_index: u32,
+    unk: u32,
+    /// Normalized coordinates of the top left corner
+    pub uv_begin: [f32; 2],
+    /// Normalized coordinates of the bottom right corner
+    pub uv_end: [f32; 2],
+    /// Position and size in pixels, with the texture flipped to be top-down
+    pub rect: Rect,
+    pub attributes: u32,
+    pub resolution_mode: ResolutionMode,
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// The screen resolution a sprite was authored for
+#[non_exhaustive]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ResolutionMode {
+    Qvga,
+    Vga,
+    Svga,
+    Xga,
+    Sxga,
+    SxgaPlus,
+    Uxga,
+    Wvga,
+    Wsvga,
+    Wxga,
+    Fwxga,
+    Wuxga,
+    Wqxga,
+    #[default]
+    Hdtv720,
+    Hdtv1080,
+    Wqhd,
+    Hvga,
+    Qhd,
+    Unknown(u32),
+}
+
+const RESOLUTION_MODES: [ResolutionMode; 18] = {
+    use ResolutionMode::*;
+    [
+        Qvga, Vga, Svga, Xga, Sxga, SxgaPlus, Uxga, Wvga, Wsvga, Wxga, Fwxga, Wuxga, Wqxga,
+        Hdtv720, Hdtv1080, Wqhd, Hvga, Qhd,
+    ]
+};
+
+impl From<u32> for ResolutionMode {
+    fn from(id: u32) -> Self {
+        RESOLUTION_MODES
+            .get(id as usize)
+            .copied()
+            .unwrap_or(Self::Unknown(id))
+    }
+}
+
+impl From<ResolutionMode> for u32 {
+    fn from(mode: ResolutionMode) -> Self {
+        match mode {
+            ResolutionMode::Unknown(id) => id,
+            mode => RESOLUTION_MODES.iter().position(|&x| x == mode).unwrap() as u32,
+        }
+    }
+}
+
+impl Sprite {
+    /// A sprite covering `rect` of a texture of `texture_size`, the uv coordinates are derived from it
+    pub fn new(
+        name: impl Into<String>,
+        texture_index: u32,
+        rect: Rect,
+        texture_size: (u32, u32),
+    ) -> Self {
+        let (width, height) = (texture_size.0 as f32, texture_size.1 as f32);
+        Self {
+            name: name.into(),
+            texture_index,
+            uv_begin: [rect.x / width, rect.y / height],
+            uv_end: [
+                (rect.x + rect.width) / width,
+                (rect.y + rect.height) / height,
+            ],
+            rect,
+            ..Default::default()
+        }
+    }
+
+    /// The word following the texture index
+    pub fn unk(&self) -> u32 {
+        self.unk
+    }
+}
+
+impl<'a> SpriteSet<'a> {
+    pub fn new(atlas: TextureAtlas<'a>, texture_names: Vec<String>, sprites: Vec<Sprite>) -> Self {
+        Self {
+            atlas,
+            texture_names,
+            sprites,
+            unk: 0,
+        }
+    }
+
+    /// The first word of the header
+    pub fn unk(&self) -> u32 {
+        self.unk
+    }
+
+    /// Parses a sprite set, reporting why the file could not be read
+    pub fn from_bytes(input: &'a [u8]) -> Result<Self, Error> {
+        let (_, set) = Self::parse(input)?;
+        Ok(set)
+    }
+
+    #[tracing::instrument(name = "spr", skip(i0))]
+    pub fn parse(i0: &'a [u8]) -> IResult<&'a [u8], SpriteSet<'a>> {
+        let endian = detect_endianness(i0);
+        let (i, unk) = u32(endian)(i0)?;
+        let (i, textures_offset) = u32(endian)(i)?;
+        let (i, texture_count) = u32(endian)(i)?;
+        let (i, sprite_count) = u32(endian)(i)?;
+        let (i, sprites_offset) = u32(endian)(i)?;
+        let (i, texture_names_offset) = u32(endian)(i)?;
+        let (i, sprite_names_offset) = u32(endian)(i)?;
+        let (i, modes_offset) = u32(endian)(i)?;
+        debug!(?endian, unk, texture_count, sprite_count);
+
+        let at = |offset: u32| {
+            i0.get(offset as usize..)
+                .ok_or(nom::Err::Failure(Error::OffsetOutOfBounds {
+                    offset: offset as usize,
+                    len: i0.len(),
+                }))
+        };
+        let (_, atlas) = cut(TextureAtlas::parse)(at(textures_offset)?)?;
+        let (_, texture_names) = offset_table(i0, parse_string, texture_count, endian)
+            .parse(at(texture_names_offset)?)?;
+        let (_, names) =
+            offset_table(i0, parse_string, sprite_count, endian).parse(at(sprite_names_offset)?)?;
+        let (_, sprites) = counted(sprite_count, SPRITE_LEN, |i| Sprite::parse(i, endian))
+            .parse(at(sprites_offset)?)?;
+        let mode = |i| {
+            let (i, attributes) = u32(endian)(i)?;
+            let (i, resolution_mode) = u32(endian)(i)?;
+            Ok((i, (attributes, resolution_mode)))
+        };
+        let (_, modes) = counted(sprite_count, MODE_LEN, mode).parse(at(modes_offset)?)?;
+
+        let sprites = sprites
+            .into_iter()
+            .zip(names)
+            .zip(modes)
+            .map(|((sprite, name), (attributes, mode))| Sprite {
+                name,
+                attributes,
+                resolution_mode: mode.into(),
+                ..sprite
+            })
+            .collect();
+        Ok((
+            i,
+            Self {
+                atlas,
+                texture_names,
+                sprites,
+                unk,
+            },
+        ))
+    }
+
+    /// Offsets of the sprite modes, the names and the atlas, followed by the length of the file
+    fn layout(&self) -> [usize; 5] {
+        let sprites = self.sprites.len();
+        let modes = HEADER_LEN + SPRITE_LEN * sprites;
+        let texture_names = modes + MODE_LEN * sprites;
+        let sprite_names = texture_names + 4 * self.atlas.textures.len();
+        let strings = sprite_names + 4 * sprites;
+        let strings_len: usize = self.names().map(|x| x.len() + 1).sum();
+        let atlas = (strings + strings_len).next_multiple_of(ATLAS_ALIGN);
+        [
+            modes,
+            texture_names,
+            sprite_names,
+            atlas,
+            atlas + self.atlas.byte_len(),
+        ]
+    }
+
+    /// The texture names, one for each texture of the atlas, followed by the sprite names
+    fn names(&self) -> impl Iterator<Item = &str> {
+        let textures = (0..self.atlas.textures.len())
+            .map(move |i| self.texture_names.get(i).map_or("", |x| &x[..]));
+        textures.chain(self.sprites.iter().map(|x| &x.name[..]))
+    }
+
+    /// Size in bytes of the serialized sprite set
+    pub fn byte_len(&self) -> usize {
+        self.layout()[4]
+    }
+
+    /// Writes the sprite set with the byte order of its atlas
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let endian = self.atlas.endianness();
+        let [modes, texture_names, sprite_names, atlas, _] = self.layout();
+        let textures = self.atlas.textures.len();
+        for x in [
+            self.unk,
+            atlas as u32,
+            textures as u32,
+            self.sprites.len() as u32,
+            HEADER_LEN as u32,
+            texture_names as u32,
+            sprite_names as u32,
+            modes as u32,
+        ] {
+            write_u32(w, x, endian)?;
+        }
+        for sprite in &self.sprites {
+            sprite.write(w, endian)?;
+        }
+        for sprite in &self.sprites {
+            write_u32(w, sprite.attributes, endian)?;
+            write_u32(w, sprite.resolution_mode.into(), endian)?;
+        }
+        let mut offset = sprite_names + 4 * self.sprites.len();
+        for name in self.names() {
+            write_u32(w, offset as u32, endian)?;
+            offset += name.len() + 1;
+        }
+        for name in self.names() {
+            w.write_all(name.as_bytes())?;
+            w.write_all(&[0])?;
+        }
+        w.write_all(&vec![0; atlas - offset])?;
+        self.atlas.write(w)
+    }
+
+    /// Fails when the atlas cannot be written, see [`TextureAtlas::to_bytes`]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        write_vec(self.byte_len(), |w| self.write(w))
+    }
+}
+
+impl Sprite {
+    fn parse(i: &[u8], endian: nom::number::Endianness) -> IResult<&[u8], Sprite> {
+        let (i, texture_index) = u32(endian)(i)?;
+        let (i, unk) = u32(endian)(i)?;
+        let (i, f) = count(f32(endian), 8)(i)?;
+        let sprite = Self {
+            texture_index,
+            unk,
+            uv_begin: [f[0], f[1]],
+            uv_end: [f[2], f[3]],
+            rect: Rect {
+                x: f[4],
+                y: f[5],
+                width: f[6],
+                height: f[7],
+            },
+            ..Default::default()
+        };
+        Ok((i, sprite))
+    }
+
+    fn write<W: Write>(&self, w: &mut W, endian: Endianness) -> io::Result<()> {
+        write_u32(w, self.texture_index, endian)?;
+        write_u32(w, self.unk, endian)?;
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = self.rect;
+        let [u0, v0] = self.uv_begin;
+        let [u1, v1] = self.uv_end;
+        for f in [u0, v0, u1, v1, x, y, width, height] {
+            write_u32(w, f.to_bits(), endian)?;
+        }
+        Ok(())
+    }
+}
+
+/// The offset of the atlas only points inside the file when read in the right byte order
+fn detect_endianness(i: &[u8]) -> nom::number::Endianness {
+    match i
+        .get(4..8)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+    {
+        Some(offset) if offset as usize >= i.len() => nom::number::Endianness::Big,
+        _ => nom::number::Endianness::Little,
+    }
+}
+
+/// Runs `f` `cnt` times, after checking that `cnt` entries of `size` bytes fit in the input
+fn counted<'a, F, O>(cnt: u32, size: usize, f: F) -> impl Parser<&'a [u8], Vec<O>, Error>
+where
+    F: Parser<&'a [u8], O, Error>,
+{
+    let mut f = count(f, cnt as usize);
+    move |i: &'a [u8]| {
+        if (cnt as usize).saturating_mul(size) > i.len() {
+            return Err(nom::Err::Failure(Error::CountOverflow(cnt)));
+        }
+        f.parse(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Only the atlas of a sprite set, the sprite table is made up from the names the games use
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    fn sprite_set() -> SpriteSet<'static> {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let mut jacket = Sprite::new(
+            "SONG_JK616",
+            1,
+            Rect {
+                x: 2.0,
+                y: 2.0,
+                width: 500.0,
+                height: 500.0,
+            },
+            (2048, 1024),
+        );
+        jacket.resolution_mode = ResolutionMode::Hdtv1080;
+        let mut logo = Sprite::new(
+            "SONG_LOGO616",
+            0,
+            Rect {
+                x: 0.0,
+                y: 256.0,
+                width: 1024.0,
+                height: 256.0,
+            },
+            (1024, 512),
+        );
+        logo.attributes = 1;
+        let names = vec!["MERGE_BC5COMP_0".into(), "MERGE_D5COMP_1".into()];
+        SpriteSet::new(atlas, names, vec![jacket, logo])
+    }
+
+    #[test]
+    fn roundtrip_sprite_set() {
+        let set = sprite_set();
+        let bytes = set.to_bytes().unwrap();
+        assert_eq!(bytes.len(), set.byte_len());
+        let atlas = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert_eq!(atlas % ATLAS_ALIGN, 0);
+        assert!(bytes[atlas..] == *SPR);
+
+        let read = SpriteSet::from_bytes(&bytes).unwrap();
+        assert_eq!(read, set);
+        assert_eq!(read.sprites[0].uv_end, [502.0 / 2048.0, 502.0 / 1024.0]);
+        assert_eq!(read.sprites[0].resolution_mode, ResolutionMode::Hdtv1080);
+    }
+
+    #[test]
+    fn roundtrip_big_endian() {
+        let mut set = sprite_set();
+        set.atlas.set_endianness(Endianness::Big);
+        set.sprites[1].resolution_mode = ResolutionMode::Unknown(99);
+        let bytes = set.to_bytes().unwrap();
+        assert_eq!(bytes[4..8], (bytes.len() - SPR.len()).to_be_bytes()[4..]);
+        assert_eq!(SpriteSet::from_bytes(&bytes).unwrap(), set);
+    }
+
+    #[test]
+    fn resolution_modes() {
+        assert_eq!(ResolutionMode::from(13), ResolutionMode::Hdtv720);
+        assert_eq!(u32::from(ResolutionMode::Hdtv1080), 14);
+        assert_eq!(ResolutionMode::from(18), ResolutionMode::Unknown(18));
+    }
+}
@@ -0,0 +1,237 @@
+//! Texture databases (`tex_db.bin`), mapping the ids objects refer to textures by to their names.
+//!
+//! The file starts with the number of entries and the offset of the entry table. Every entry is
+//! an id followed by the offset of its NUL-terminated name, all offsets are relative to the start
+//! of the file.
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+use nom::multi::count;
+use nom::number::complete::u32;
+use tracing::debug;
+
+use super::read::{parse_string, IResult};
+use super::write::write_u32;
+use super::*;
+
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 8;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TexDb {
+    pub entries: Vec<TexDbEntry>,
+    endianness: Endianness,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct TexDbEntry {
+    pub id: u32,
+    pub name: String,
+}
+
+impl TexDb {
+    pub fn new(entries: Vec<TexDbEntry>) -> Self {
+        Self {
+            entries,
+            endianness: Endianness::default(),
+        }
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// The name of the texture with this id
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|x| x.id == id)
+            .map(|x| &x.name[..])
+    }
+
+    /// The id of the texture with this name
+    pub fn id(&self, name: &str) -> Option<u32> {
+        self.entries.iter().find(|x| x.name == name).map(|x| x.id)
+    }
+
+    /// Names the textures of `atlas` whose id is set, see [`TextureAtlas::set_ids`]
+    pub fn annotate(&self, atlas: &mut TextureAtlas) {
+        for tex in &mut atlas.textures {
+            if let Some(name) = tex.id.and_then(|id| self.name(id)) {
+                tex.name = Some(name.into());
+            }
+        }
+    }
+
+    /// Parses a database, reporting why the file could not be read
+    pub fn from_bytes(input: &[u8]) -> Result<Self, Error> {
+        let (_, db) = Self::parse(input)?;
+        Ok(db)
+    }
+
+    #[tracing::instrument(name = "tex_db", skip(i0))]
+    pub fn parse(i0: &[u8]) -> IResult<&[u8], TexDb> {
+        // the entry table only lies inside the file when read in the right byte order
+        let endian = match i0
+            .get(4..8)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        {
+            Some(offset) if offset as usize > i0.len() => nom::number::Endianness::Big,
+            _ => nom::number::Endianness::Little,
+        };
+        let (i, cnt) = u32(endian)(i0)?;
+        let (i, offset) = u32(endian)(i)?;
+        debug!(?endian, cnt, offset);
+
+        let table =
+            i0.get(offset as usize..)
+                .ok_or(nom::Err::Failure(Error::OffsetOutOfBounds {
+                    offset: offset as usize,
+                    len: i0.len(),
+                }))?;
+        if (cnt as usize).saturating_mul(ENTRY_LEN) > table.len() {
+            return Err(nom::Err::Failure(Error::CountOverflow(cnt)));
+        }
+        let entry = |i| {
+            let (i, id) = u32(endian)(i)?;
+            let (i, name_offset) = u32(endian)(i)?;
+            let name = i0.get(name_offset as usize..).ok_or(nom::Err::Failure(
+                Error::OffsetOutOfBounds {
+                    offset: name_offset as usize,
+                    len: i0.len(),
+                },
+            ))?;
+            let (_, name) = parse_string(name)?;
+            Ok((i, TexDbEntry { id, name }))
+        };
+        let (_, entries) = count(entry, cnt as usize)(table)?;
+        Ok((
+            i,
+            Self {
+                entries,
+                endianness: endian.into(),
+            },
+        ))
+    }
+
+    /// Size in bytes of the serialized database
+    pub fn byte_len(&self) -> usize {
+        let names: usize = self.entries.iter().map(|x| x.name.len() + 1).sum();
+        HEADER_LEN + ENTRY_LEN * self.entries.len() + names
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let endian = self.endianness;
+        write_u32(w, self.entries.len() as u32, endian)?;
+        write_u32(w, HEADER_LEN as u32, endian)?;
+        let mut offset = HEADER_LEN + ENTRY_LEN * self.entries.len();
+        for entry in &self.entries {
+            write_u32(w, entry.id, endian)?;
+            write_u32(w, offset as u32, endian)?;
+            offset += entry.name.len() + 1;
+        }
+        for entry in &self.entries {
+            w.write_all(entry.name.as_bytes())?;
+            w.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.byte_len());
+        self.write(&mut vec).expect("writing to a Vec cannot fail");
+        vec
+    }
+}
+
+impl TextureAtlas<'_> {
+    /// Sets the id of every texture, in order, as listed by the object set using the atlas
+    pub fn set_ids(&mut self, ids: impl IntoIterator<Item = u32>) {
+        for (tex, id) in self.textures.iter_mut().zip(ids) {
+            tex.id = Some(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+
+    /// No tex_db.bin from the games is in the test assets, the names are made up
+    fn tex_db() -> TexDb {
+        let entries = (0..3)
+            .map(|i| TexDbEntry {
+                id: 0x1000 + i,
+                name: format!("F_DIVA_MIK001_ITM_{}", i),
+            })
+            .collect();
+        TexDb::new(entries)
+    }
+
+    #[test]
+    fn roundtrip_tex_db() {
+        let mut db = tex_db();
+        let bytes = db.to_bytes();
+        assert_eq!(bytes.len(), db.byte_len());
+        assert_eq!(TexDb::from_bytes(&bytes).unwrap(), db);
+
+        db.set_endianness(Endianness::Big);
+        let bytes = db.to_bytes();
+        assert_eq!(bytes[..4], [0, 0, 0, 3]);
+        assert_eq!(TexDb::from_bytes(&bytes).unwrap(), db);
+    }
+
+    #[test]
+    fn annotate() {
+        let db = tex_db();
+        assert_eq!(db.name(0x1001), Some("F_DIVA_MIK001_ITM_1"));
+        assert_eq!(db.id("F_DIVA_MIK001_ITM_2"), Some(0x1002));
+
+        let mut atlas = TextureAtlas::from_bytes(MIKITM).unwrap();
+        atlas.set_ids([0x1002, 0x1000, 0xDEAD]);
+        db.annotate(&mut atlas);
+        let names: Vec<_> = atlas.textures[..4]
+            .iter()
+            .map(|x| x.name.as_deref())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                Some("F_DIVA_MIK001_ITM_2"),
+                Some("F_DIVA_MIK001_ITM_0"),
+                None,
+                None
+            ]
+        );
+        assert_eq!(atlas.textures[2].id, Some(0xDEAD));
+    }
+
+    #[test]
+    fn empty() {
+        let db = TexDb::default();
+        assert_eq!(TexDb::from_bytes(&db.to_bytes()).unwrap(), db);
+    }
+
+    #[test]
+    fn bad_offsets() {
+        let mut bytes = tex_db().to_bytes();
+        bytes[12..16].copy_from_slice(&0x1000u32.to_le_bytes());
+        assert_eq!(
+            TexDb::from_bytes(&bytes),
+            Err(Error::OffsetOutOfBounds {
+                offset: 0x1000,
+                len: bytes.len()
+            })
+        );
+        assert_eq!(
+            TexDb::from_bytes(&[0xFF, 0, 0, 0, 8, 0, 0, 0]),
+            Err(Error::CountOverflow(0xFF))
+        );
+    }
+}
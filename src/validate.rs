@@ -0,0 +1,253 @@
+//! Consistency checks of an atlas, see [`TextureAtlas::validate`].
+use std::fmt;
+
+use super::*;
+
+/// Where a mipmap is in an atlas
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MipLocation {
+    pub texture: usize,
+    pub subtexture: usize,
+    pub level: usize,
+}
+
+/// A problem found by [`TextureAtlas::validate`]
+#[non_exhaustive]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Issue {
+    /// The data of a mipmap does not match its dimensions and format
+    SizeMismatch {
+        at: MipLocation,
+        expected: usize,
+        found: usize,
+    },
+    /// A mipmap is not half the size of the previous level
+    NotHalved {
+        at: MipLocation,
+        expected: (u32, u32),
+        found: (u32, u32),
+    },
+    /// A mipmap has another format than the base level of its texture
+    FormatMismatch {
+        at: MipLocation,
+        expected: TextureFormat,
+        found: TextureFormat,
+    },
+    /// A face or layer has other dimensions, or another number of mipmaps, than the first one
+    FaceMismatch {
+        texture: usize,
+        subtexture: usize,
+        expected: (u32, u32, usize),
+        found: (u32, u32, usize),
+    },
+    /// A cubemap does not have six faces
+    CubeFaces { texture: usize, found: usize },
+}
+
+impl fmt::Display for MipLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "texture #{} subtexture #{} mipmap #{}",
+            self.texture, self.subtexture, self.level
+        )
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SizeMismatch {
+                at,
+                expected,
+                found,
+            } => write!(f, "{}: expected {} bytes, found {}", at, expected, found),
+            Self::NotHalved {
+                at,
+                expected: (w, h),
+                found: (fw, fh),
+            } => write!(f, "{}: expected {}x{}, found {}x{}", at, w, h, fw, fh),
+            Self::FormatMismatch {
+                at,
+                expected,
+                found,
+            } => write!(f, "{}: expected {:?}, found {:?}", at, expected, found),
+            Self::FaceMismatch {
+                texture,
+                subtexture,
+                expected: (w, h, n),
+                found: (fw, fh, fn_),
+            } => write!(
+                f,
+                "texture #{} subtexture #{}: expected {}x{} with {} mipmaps, found {}x{} with {}",
+                texture, subtexture, w, h, n, fw, fh, fn_
+            ),
+            Self::CubeFaces { texture, found } => {
+                write!(
+                    f,
+                    "texture #{}: expected 6 cubemap faces, found {}",
+                    texture, found
+                )
+            }
+        }
+    }
+}
+
+impl TextureAtlas<'_> {
+    /// Checks the size of every mipmap against its data and the rest of its texture.
+    ///
+    /// Mipmaps of [`TextureFormat::unpadded_len`] bytes and sides halved down to 0, the way the
+    /// games store them, are both accepted. Sides halved down to 1 are accepted too, this is how
+    /// `Subtexture::generate_mipmaps` sizes the levels it encodes.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = vec![];
+        for (texture, tex) in self.textures.iter().enumerate() {
+            tex.validate(texture, &mut issues);
+        }
+        issues
+    }
+}
+
+impl Texture<'_> {
+    fn validate(&self, texture: usize, issues: &mut Vec<Issue>) {
+        let base = self.subtextures.first().and_then(|x| x.mipmaps.first());
+        let shape = |subtex: &Subtexture| {
+            let first = subtex.mipmaps.first();
+            let (w, h) = first.map_or((0, 0), |x| (x.width, x.height));
+            (w, h, subtex.mipmaps.len())
+        };
+        let first = self.subtextures.first().map(shape);
+        if self.kind == TextureKind::Cube && self.subtextures.len() != 6 {
+            issues.push(Issue::CubeFaces {
+                texture,
+                found: self.subtextures.len(),
+            });
+        }
+        for (subtexture, subtex) in self.subtextures.iter().enumerate() {
+            if let Some(expected) = first.filter(|&x| x != shape(subtex)) {
+                issues.push(Issue::FaceMismatch {
+                    texture,
+                    subtexture,
+                    expected,
+                    found: shape(subtex),
+                });
+            }
+            let mut previous: Option<&Mipmap> = None;
+            for (level, mip) in subtex.mipmaps.iter().enumerate() {
+                let at = MipLocation {
+                    texture,
+                    subtexture,
+                    level,
+                };
+                let found = mip.data.len();
+                let expected = mip.expected_len();
+                if found != expected && found != mip.format.unpadded_len(mip.width, mip.height) {
+                    issues.push(Issue::SizeMismatch {
+                        at,
+                        expected,
+                        found,
+                    });
+                }
+                if let Some(base) = base.filter(|x| x.format != mip.format) {
+                    issues.push(Issue::FormatMismatch {
+                        at,
+                        expected: base.format,
+                        found: mip.format,
+                    });
+                }
+                if let Some(prev) = previous {
+                    let halved = |x: u32, prev: u32| x == prev / 2 || x == (prev / 2).max(1);
+                    if !halved(mip.width, prev.width) || !halved(mip.height, prev.height) {
+                        issues.push(Issue::NotHalved {
+                            at,
+                            expected: ((prev.width / 2).max(1), (prev.height / 2).max(1)),
+                            found: (mip.width, mip.height),
+                        });
+                    }
+                }
+                previous = Some(mip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    #[test]
+    fn game_files_are_valid() {
+        assert_eq!(TextureAtlas::from_bytes(MIKITM).unwrap().validate(), []);
+        assert_eq!(TextureAtlas::from_bytes(SPR).unwrap().validate(), []);
+    }
+
+    #[test]
+    fn issues() {
+        let mut atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let at = |level| MipLocation {
+            texture: 0,
+            subtexture: 0,
+            level,
+        };
+        {
+            let mips = &mut atlas.textures[0].subtextures[0].mipmaps;
+            mips[0].data.to_mut().pop();
+            mips[1].width = 500;
+            mips[1].format = TextureFormat::ATI1;
+        }
+        let face = atlas.textures[1].subtextures[0].clone();
+        let mut small = face.clone();
+        small.mipmaps[0].width = 1024;
+        atlas.textures[1] = Texture::new(vec![face, small]);
+        atlas.textures[0].kind = TextureKind::Cube;
+
+        let issues = atlas.validate();
+        assert_eq!(
+            issues,
+            [
+                Issue::CubeFaces {
+                    texture: 0,
+                    found: 1
+                },
+                Issue::SizeMismatch {
+                    at: at(0),
+                    expected: 1024 * 512,
+                    found: 1024 * 512 - 1
+                },
+                Issue::SizeMismatch {
+                    at: at(1),
+                    expected: 125 * 64 * 8,
+                    found: 512 * 256
+                },
+                Issue::FormatMismatch {
+                    at: at(1),
+                    expected: TextureFormat::ATI2,
+                    found: TextureFormat::ATI1
+                },
+                Issue::NotHalved {
+                    at: at(1),
+                    expected: (512, 256),
+                    found: (500, 256)
+                },
+                Issue::FaceMismatch {
+                    texture: 1,
+                    subtexture: 1,
+                    expected: (2048, 1024, 1),
+                    found: (1024, 1024, 1)
+                },
+                Issue::SizeMismatch {
+                    at: MipLocation {
+                        texture: 1,
+                        subtexture: 1,
+                        level: 0
+                    },
+                    expected: 1024 * 1024,
+                    found: 2048 * 1024
+                },
+            ]
+        );
+    }
+}
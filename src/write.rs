@@ -0,0 +1,308 @@
+use std::io::{self, Write};
+
+use tracing::{debug, trace};
+
+use super::section::{self, Section};
+use super::*;
+
+const SECTION_ALIGN: usize = 0x10;
+
+fn write_magic<W: Write>(w: &mut W, id: u8, endian: Endianness) -> io::Result<()> {
+    write_u32(w, u32::from_le_bytes([0x54, 0x58, 0x50, id]), endian)
+}
+
+pub(crate) fn write_u32<W: Write>(w: &mut W, v: u32, endian: Endianness) -> io::Result<()> {
+    match endian {
+        Endianness::Little => w.write_all(&v.to_le_bytes()),
+        Endianness::Big => w.write_all(&v.to_be_bytes()),
+    }
+}
+
+/// Writes into a new `Vec`, the only errors are the ones the writers raise for input they cannot
+/// represent
+pub(crate) fn write_vec(
+    len: usize,
+    write: impl FnOnce(&mut Vec<u8>) -> io::Result<()>,
+) -> Result<Vec<u8>, Error> {
+    let mut vec = Vec::with_capacity(len);
+    match write(&mut vec) {
+        Ok(()) => Ok(vec),
+        Err(e) => match e.into_inner().map(|e| e.downcast::<Error>()) {
+            Some(Ok(e)) => Err(*e),
+            _ => unreachable!("writing to a Vec cannot fail"),
+        },
+    }
+}
+
+/// Writes an offset table for `sizes`, with the first entry starting at `start`
+fn write_offsets<W: Write>(
+    w: &mut W,
+    start: usize,
+    sizes: impl Iterator<Item = usize>,
+    endian: Endianness,
+) -> io::Result<()> {
+    let mut offset = start;
+    for size in sizes {
+        write_u32(w, offset as u32, endian)?;
+        offset += size;
+    }
+    Ok(())
+}
+
+impl TextureAtlas<'_> {
+    fn classic_len(&self) -> usize {
+        12 + 4 * self.textures.len() + self.textures.iter().map(Texture::byte_len).sum::<usize>()
+    }
+
+    /// Size in bytes of the serialized atlas
+    pub fn byte_len(&self) -> usize {
+        match self.revision {
+            Revision::Classic => self.classic_len(),
+            Revision::Modern => {
+                section::HEADER_LEN as usize * 2
+                    + self.classic_len().next_multiple_of(SECTION_ALIGN)
+            }
+        }
+    }
+
+    /// Writes the atlas in the layout of its [`Revision`]
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self.revision {
+            Revision::Classic => self.write_classic(w),
+            Revision::Modern => {
+                let len = self.classic_len();
+                let padded = len.next_multiple_of(SECTION_ALIGN);
+                let mut section = Section::new(*b"MTXD", padded as u32, self.endianness);
+                if let Some(read) = &self.section {
+                    section = section.with_header_of(read);
+                }
+                section.write(w)?;
+                self.write_classic(w)?;
+                w.write_all(&vec![0; padded - len])?;
+                Section::eofc(self.endianness).write(w)
+            }
+        }
+    }
+
+    /// The low byte of the header word read alongside the atlas is the texture count, as in
+    /// `0x0101_0112` for the 18 textures of `mikitm001_tex.txp`. It is rewritten so it stays in
+    /// step when textures are added or removed, the rest of the word is kept as-is. Atlases of more
+    /// than 255 textures are rejected with [`io::ErrorKind::InvalidInput`].
+    #[tracing::instrument(name = "atlas", skip_all)]
+    fn write_classic<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let count = self.textures.len();
+        let endian = self.endianness;
+        debug!(count, ?endian, self.unk);
+        if count > 0xFF {
+            let e = Error::Unrepresentable(format!("an atlas of {} textures", count));
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+        write_magic(w, 3, endian)?;
+        write_u32(w, count as u32, endian)?;
+        write_u32(w, (self.unk & !0xFF) | count as u32, endian)?;
+        write_offsets(
+            w,
+            12 + 4 * count,
+            self.textures.iter().map(Texture::byte_len),
+            endian,
+        )?;
+        for tex in &self.textures {
+            tex.write(w)?;
+        }
+        Ok(())
+    }
+
+    /// Fails on atlases of more than 255 textures, and on the textures [`Texture::write`] rejects
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        write_vec(self.byte_len(), |w| self.write(w))
+    }
+}
+
+impl Texture<'_> {
+    pub(crate) fn mipmaps(&self) -> impl Iterator<Item = &Mipmap<'_>> {
+        self.subtextures.iter().flat_map(|x| x.mipmaps.iter())
+    }
+
+    /// Size in bytes of the serialized texture
+    pub fn byte_len(&self) -> usize {
+        let count = self.mipmaps().count();
+        12 + 4 * count + self.mipmaps().map(Mipmap::byte_len).sum::<usize>()
+    }
+
+    /// 2D textures are written as `TXP\x04`, cubemaps and arrays as `TXP\x05`
+    ///
+    /// The header word read alongside the texture is kept as-is, except for the depth byte. Arrays
+    /// of 6 layers are rejected with [`io::ErrorKind::InvalidInput`], they would be read back as
+    /// cubemaps.
+    #[tracing::instrument(name = "texture", skip_all)]
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let depth = self.subtextures.len();
+        let total = self.mipmaps().count();
+        let endian = self.endianness;
+        debug!(depth, total, ?endian, self.unk);
+        if self.kind == TextureKind::Array && depth == 6 {
+            let e = Error::Unrepresentable("an array of 6 layers".into());
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+        }
+        let is_2d = self.kind == TextureKind::TwoD && depth <= 1;
+        write_magic(w, if is_2d { 4 } else { 5 }, endian)?;
+        write_u32(w, total as u32, endian)?;
+        write_u32(
+            w,
+            (self.unk & !0xFF00) | ((depth.max(1) as u32 & 0xFF) << 8),
+            endian,
+        )?;
+        write_offsets(
+            w,
+            12 + 4 * total,
+            self.mipmaps().map(Mipmap::byte_len),
+            endian,
+        )?;
+        for mip in self.mipmaps() {
+            mip.write(w)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [`Error::Unrepresentable`] on arrays of 6 layers, see [`Texture::write`]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        write_vec(self.byte_len(), |w| self.write(w))
+    }
+}
+
+impl Mipmap<'_> {
+    /// Size in bytes of the serialized mipmap
+    pub fn byte_len(&self) -> usize {
+        24 + self.data.len()
+    }
+
+    #[tracing::instrument(name = "mip", skip_all)]
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let endian = self.endianness;
+        trace!(self.width, self.height, ?self.format, self.id, ?endian);
+        write_magic(w, 2, endian)?;
+        write_u32(w, self.width, endian)?;
+        write_u32(w, self.height, endian)?;
+        write_u32(w, self.format.to_id(), endian)?;
+        write_u32(w, self.id, endian)?;
+        write_u32(w, self.data.len() as u32, endian)?;
+        w.write_all(&self.data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.byte_len());
+        self.write(&mut vec).expect("writing to a Vec cannot fail");
+        vec
+    }
+}
+
+impl TextureFormat {
+    pub(crate) fn to_id(self) -> u32 {
+        self as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIKITM: &[u8] = include_bytes!("../assets/mikitm001_tex.txp");
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    #[test]
+    fn roundtrip_atlas() {
+        let (_, atlas) = TextureAtlas::parse(MIKITM).unwrap();
+        assert_eq!(atlas.byte_len(), MIKITM.len());
+        assert!(atlas.to_bytes().unwrap() == MIKITM);
+        assert_eq!(atlas.unk(), 0x0101_0112);
+
+        // the low byte of the header word follows the texture count
+        let mut atlas = atlas;
+        atlas.textures.truncate(3);
+        let bytes = atlas.to_bytes().unwrap();
+        let (_, read) = TextureAtlas::parse(&bytes).unwrap();
+        assert_eq!(read.unk(), 0x0101_0103);
+        let tex = atlas.textures[0].clone();
+        atlas.textures.resize(256, tex);
+        assert_eq!(
+            atlas.to_bytes(),
+            Err(Error::Unrepresentable("an atlas of 256 textures".into()))
+        );
+    }
+
+    #[test]
+    fn roundtrip_multiple_mips() {
+        let (_, atlas) = TextureAtlas::parse(SPR).unwrap();
+        assert!(atlas.to_bytes().unwrap() == SPR);
+    }
+
+    #[test]
+    fn roundtrip_modern() {
+        let (_, mut atlas) = TextureAtlas::parse(MIKITM).unwrap();
+        atlas.revision = Revision::Modern;
+        let bytes = atlas.to_bytes().unwrap();
+        assert_eq!(bytes.len(), atlas.byte_len());
+        assert_eq!(&bytes[..4], b"MTXD");
+        assert_eq!(&bytes[bytes.len() - 0x20..][..4], b"EOFC");
+        let (rest, read) = TextureAtlas::parse(&bytes).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(read.textures, atlas.textures);
+        assert!(read.to_bytes().unwrap() == bytes);
+
+        // flags and depth the games may set are kept
+        let mut bytes = bytes;
+        bytes[12..16].copy_from_slice(&0x1000_0004u32.to_le_bytes());
+        bytes[16..20].copy_from_slice(&1u32.to_le_bytes());
+        let read = TextureAtlas::from_bytes(&bytes).unwrap();
+        assert!(read.to_bytes().unwrap() == bytes);
+    }
+
+    #[test]
+    fn roundtrip_big_endian() {
+        let (_, mut atlas) = TextureAtlas::parse(SPR).unwrap();
+        atlas.set_endianness(Endianness::Big);
+        let bytes = atlas.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], b"\x03PXT");
+        let (_, read) = TextureAtlas::parse(&bytes).unwrap();
+        assert_eq!(read.endianness(), Endianness::Big);
+        assert_eq!(read.textures[0].endianness(), Endianness::Big);
+        assert_eq!(read.textures[0].subtextures[0].mipmaps[1].id(), 1);
+        assert_eq!(read, atlas);
+        assert!(read.to_bytes().unwrap() == bytes);
+    }
+
+    #[test]
+    fn roundtrip_array() {
+        let (_, atlas) = TextureAtlas::parse(MIKITM).unwrap();
+        let face = &atlas.textures[0].subtextures[0];
+        let tex = Texture::new_cube([(); 6].map(|_| face.clone()));
+        let bytes = tex.to_bytes().unwrap();
+        let (_, read) = Texture::parse_array(&bytes).unwrap();
+        assert_eq!(read, tex);
+        assert_eq!(read.cube_face(Face::NegativeY), Some(face));
+
+        let tex = Texture::new(vec![face.clone(); 3]);
+        let bytes = tex.to_bytes().unwrap();
+        let (_, read) = Texture::parse_array(&bytes).unwrap();
+        assert_eq!(read.kind, TextureKind::Array);
+        assert_eq!(read.cube_face(Face::PositiveX), None);
+        assert_eq!(read, tex);
+
+        let tex = Texture::new(vec![face.clone(); 6]);
+        let err = tex.write(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            tex.to_bytes(),
+            Err(Error::Unrepresentable("an array of 6 layers".into()))
+        );
+        let atlas = TextureAtlas::new(vec![tex]);
+        assert_eq!(
+            atlas.to_bytes(),
+            Err(Error::Unrepresentable("an array of 6 layers".into()))
+        );
+        let tex = Texture::new(vec![face.clone(); 7]);
+        let bytes = tex.to_bytes().unwrap();
+        let (_, read) = Texture::parse_array(&bytes).unwrap();
+        assert_eq!(read, tex);
+    }
+}
@@ -1,28 +1,35 @@
+//! YUV textures, used by sprite sets for detailed images such as the song thumbnails.
+//!
+//! They are a single subtexture with two ATI2 levels. The full size level holds the luma in red
+//! and the alpha in green, the half size level holds Cb in red and Cr in green.
 #[cfg(feature = "image")]
-use ::image::{Bgr, Bgra, DynamicImage, ImageBuffer, Rgb, Rgba};
-use dcv_color_primitives::*;
-use tracing::{debug, instrument, trace};
+use ::image::{imageops, ImageBuffer, Rgba, RgbaImage};
 
+#[cfg(feature = "image")]
+use super::encode;
 use super::*;
-
-const NV12: ImageFormat = ImageFormat {
-    pixel_format: PixelFormat::Nv12,
-    color_space: ColorSpace::Bt709,
-    num_planes: 2,
-};
-
-const BGRA: ImageFormat = ImageFormat {
-    pixel_format: PixelFormat::Bgra,
-    color_space: ColorSpace::Lrgb,
-    num_planes: 1,
-};
+use super::{decode, flip};
+
+/// The games read the chroma as `x * CHROMA_SCALE - CHROMA_BIAS`
+const CHROMA_SCALE: f32 = 1.003922;
+const CHROMA_BIAS: f32 = 0.503929;
+/// The YCbCr to RGB matrix of the sprite shader of the games, Rec. 709
+const CR_TO_R: f32 = 1.5748;
+const CB_TO_G: f32 = -0.1873;
+const CR_TO_G: f32 = -0.4681;
+const CB_TO_B: f32 = 1.8556;
+/// Rec. 709 luma weights, the inverse of the matrix
+#[cfg(feature = "image")]
+const KR: f32 = 0.2126;
+#[cfg(feature = "image")]
+const KB: f32 = 0.0722;
 
 impl Texture<'_> {
     pub fn is_yuv(&self) -> bool {
         self.subtextures.len() == 1
             && self
                 .subtextures
-                .get(0)
+                .first()
                 .map(Subtexture::is_yuv)
                 .unwrap_or_default()
     }
@@ -33,66 +40,213 @@ impl Subtexture<'_> {
         self.mipmaps.len() == 2 && self.mipmaps.iter().all(|d| d.format == TextureFormat::ATI2)
     }
 
+    /// Decodes both levels into RGBA8 the way the games do, the chroma is sampled bilinearly
     #[tracing::instrument(skip(self))]
-    pub fn yuv_to_bgra(&self) -> Result<Vec<u8>, ErrorKind> {
-        dcv_color_primitives::initialize();
-
-        let ay = &self.mipmaps[0];
-        let uv = &self.mipmaps[1];
-
-        let src_sizes: &mut [usize] = &mut [0usize; 2];
-        get_buffers_size(ay.width, ay.height, &NV12, None, src_sizes)?;
-
-        debug!("{}x{}", ay.width, ay.height);
-        debug!("src: {}", ay.data.len());
-        trace!(?src_sizes);
-
-        let src_y: Vec<_> = vec![0u8; src_sizes[0]];
-        let src_uv: Vec<_> = vec![0u8; src_sizes[1]];
-        let src_buffers: &[&[u8]] = &[&src_y[..], &src_uv[..]];
-
-        let mut y_data: Vec<u8> = Vec::with_capacity(uv.data.len() * 2);
-        let mut uv_data: Vec<u8> = Vec::with_capacity(uv.data.len() * 2);
-        uv_data = vec![128; uv.data.len() * 2];
-        uv_data[0..uv.data.len()].copy_from_slice(&uv.data[..]);
-        uv_data[uv.data.len()..].copy_from_slice(&uv.data[..]);
-        // let uv_data: Vec<u8> = uv.data.iter().zip(uv.data.iter()).flat_map(|(a, b)| vec![a, b]).copied().collect();
-        // for b in &uv.data[..] {
-        //     uv_data.push(*b);
-        //     uv_data.push(*b);
-        // }
-
-        let yuv = &[&ay.data[..], &uv_data[..]];
-
-        let dst_sizes: &mut [usize] = &mut [0usize; 1];
-        get_buffers_size(ay.width, ay.height, &BGRA, None, dst_sizes)?;
-        trace!("yeet");
-
-        let mut dst_rgba: Vec<_> = vec![0u8; dst_sizes[0]];
-        let dst_buffers: &mut [&mut [u8]] = &mut [&mut dst_rgba[..]];
-
-        convert_image(
-            ay.width,
-            ay.height,
-            &NV12,
-            None,
-            yuv,
-            &BGRA,
-            None,
-            dst_buffers,
-        )?;
-
-        Ok(dst_rgba)
+    pub fn yuv_to_rgba(&self, orientation: Orientation) -> Result<Vec<u8>, Error> {
+        if !self.is_yuv() {
+            return Err(Error::Undecodable("a subtexture that is not YUV".into()));
+        }
+        let decode = |mip: &Mipmap| {
+            let (width, height) = (mip.width.max(1), mip.height.max(1));
+            decode::decode_rgba8(mip.format, width, height, &mip.data)
+                .map(|rgba| (width, height, rgba))
+                .ok_or_else(|| Error::Undecodable(mip.to_string()))
+        };
+        let (width, height, luma) = decode(&self.mipmaps[0])?;
+        let (chroma_width, chroma_height, chroma) = decode(&self.mipmaps[1])?;
+        let texel = |x: usize, y: usize, c: usize| chroma[(y * chroma_width as usize + x) * 4 + c];
+        // the position of the center of a pixel in the chroma level, and the texels around it
+        let project = |x: u32, size: u32, chroma_size: u32| {
+            let u = ((x as f32 + 0.5) * chroma_size as f32 / size as f32 - 0.5).max(0.0);
+            let i = (u as usize).min(chroma_size as usize - 1);
+            (i, (i + 1).min(chroma_size as usize - 1), u - i as f32)
+        };
+
+        let mut rgba = Vec::with_capacity(luma.len());
+        for y in 0..height {
+            let (y0, y1, fy) = project(y, height, chroma_height);
+            for x in 0..width {
+                let (x0, x1, fx) = project(x, width, chroma_width);
+                let sample = |c| {
+                    let lerp = |a: u8, b: u8, f: f32| a as f32 + (b as f32 - a as f32) * f;
+                    let top = lerp(texel(x0, y0, c), texel(x1, y0, c), fx);
+                    let bottom = lerp(texel(x0, y1, c), texel(x1, y1, c), fx);
+                    (top + (bottom - top) * fy) / 255.0 * CHROMA_SCALE - CHROMA_BIAS
+                };
+                let (cb, cr) = (sample(0), sample(1));
+                let px = &luma[(y * width + x) as usize * 4..][..4];
+                let l = px[0] as f32 / 255.0;
+                rgba.extend([
+                    unorm(l + CR_TO_R * cr),
+                    unorm(l + CB_TO_G * cb + CR_TO_G * cr),
+                    unorm(l + CB_TO_B * cb),
+                    px[1],
+                ]);
+            }
+        }
+        if orientation == Orientation::TopDown {
+            flip::flip_rows(&mut rgba, width as usize * 4);
+        }
+        Ok(rgba)
     }
 
     #[cfg(feature = "image")]
-    pub fn yuv_to_image(&self) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>, ErrorKind> {
-        let rgba = self.yuv_to_bgra()?;
+    pub fn yuv_to_image(&self, orientation: Orientation) -> Result<RgbaImage, Error> {
+        let rgba = self.yuv_to_rgba(orientation)?;
         let first = &self.mipmaps[0];
-        let w = first.width;
-        let h = first.height;
-        let image = ImageBuffer::from_raw(w, h, rgba).unwrap();
-        // Ok(DynamicImage::ImageRgba8(image))
-        Ok(image)
+        let image = ImageBuffer::from_raw(first.width.max(1), first.height.max(1), rgba);
+        Ok(image.expect("a pixel is decoded per pixel of the first level"))
+    }
+}
+
+#[cfg(feature = "image")]
+impl Subtexture<'_> {
+    /// Encodes `image` as a YUV texture, with the chroma averaged over every 2x2 pixels.
+    /// `image` is flipped from `orientation` to the way the games store textures.
+    pub fn from_image_yuv(
+        image: &RgbaImage,
+        orientation: Orientation,
+    ) -> Result<Subtexture<'static>, Error> {
+        let flipped;
+        let image = match orientation {
+            Orientation::TopDown => {
+                flipped = imageops::flip_vertical(image);
+                &flipped
+            }
+            Orientation::BottomUp => image,
+        };
+        let (width, height) = image.dimensions();
+        let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut luma = RgbaImage::new(width, height);
+        let mut chroma = vec![[0.0f32; 3]; (half_width * half_height) as usize];
+        for (x, y, px) in image.enumerate_pixels() {
+            let [r, g, b] = [px[0], px[1], px[2]].map(|x| x as f32 / 255.0);
+            let l = KR * r + (1.0 - KR - KB) * g + KB * b;
+            luma.put_pixel(x, y, Rgba([unorm(l), px[3], 0, 255]));
+            let (x, y) = ((x / 2).min(half_width - 1), (y / 2).min(half_height - 1));
+            let sum = &mut chroma[(y * half_width + x) as usize];
+            sum[0] += (b - l) / CB_TO_B;
+            sum[1] += (r - l) / CR_TO_R;
+            sum[2] += 1.0;
+        }
+        let chroma = chroma
+            .into_iter()
+            .flat_map(|[cb, cr, n]| {
+                let stored = |c: f32| unorm((c / n + CHROMA_BIAS) / CHROMA_SCALE);
+                [stored(cb), stored(cr), 0, 255]
+            })
+            .collect();
+        let chroma: RgbaImage = ImageBuffer::from_raw(half_width, half_height, chroma)
+            .expect("the chroma has a pixel per 2x2 block");
+
+        let level = |id, image: &RgbaImage| -> Result<Mipmap<'static>, Error> {
+            let (width, height) = image.dimensions();
+            let format = TextureFormat::ATI2;
+            let data = encode::encode(format, width, height, image.as_raw(), Quality::default())?;
+            Ok(Mipmap {
+                id,
+                width,
+                height,
+                format,
+                data: data.into(),
+                ..Default::default()
+            })
+        };
+        Ok(Subtexture {
+            mipmaps: vec![level(0, &luma)?, level(1, &chroma)?],
+        })
+    }
+}
+
+fn unorm(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPR: &[u8] = include_bytes!("../assets/spr_sel_pv616.bin");
+
+    #[cfg(feature = "image")]
+    fn solid(px: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(16, 8, Rgba(px))
+    }
+
+    #[test]
+    fn decode_pinned_colors() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let stock = &atlas.textures[0].subtextures[0];
+        let rgba = stock.yuv_to_rgba(Orientation::BottomUp).unwrap();
+        // worked out from the decoded levels with the matrix of the games, at pixels where the four
+        // chroma texels around them are equal
+        for (x, y, px) in [
+            (690, 260, [253, 49, 47, 255]),
+            (478, 334, [236, 218, 24, 255]),
+            (690, 334, [185, 183, 181, 255]),
+            (213, 445, [253, 253, 251, 255]),
+            (0, 0, [0, 0, 0, 0]),
+        ] {
+            assert_eq!(rgba[(y * 1024 + x) * 4..][..4], px, "{} {}", x, y);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn decode_like_the_games() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let stock = &atlas.textures[0].subtextures[0];
+        let image = stock.yuv_to_image(Orientation::TopDown).unwrap();
+        assert_eq!(image.dimensions(), (1024, 512));
+        let luma = stock.mipmaps[0].to_rgba8().unwrap();
+        assert!(image.pixels().zip(luma.pixels()).all(|(a, b)| a[3] == b[1]));
+
+        for px in [
+            [255, 0, 0, 255],
+            [20, 200, 90, 128],
+            [255, 255, 255, 0],
+            [0, 0, 0, 255],
+        ] {
+            let subtex = Subtexture::from_image_yuv(&solid(px), Orientation::TopDown).unwrap();
+            let decoded = subtex.yuv_to_image(Orientation::TopDown).unwrap();
+            for (a, b) in decoded.get_pixel(5, 3).0.iter().zip(px) {
+                assert!(
+                    (*a as i32 - b as i32).abs() <= 3,
+                    "{:?} {:?}",
+                    decoded.get_pixel(5, 3),
+                    px
+                );
+            }
+        }
+        let not_yuv = &atlas.textures[1].subtextures[0];
+        assert!(matches!(
+            not_yuv.yuv_to_rgba(Orientation::TopDown),
+            Err(Error::Undecodable(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn encode_like_the_games() {
+        let atlas = TextureAtlas::from_bytes(SPR).unwrap();
+        let stock = &atlas.textures[0].subtextures[0];
+        assert!(stock.is_yuv());
+        let image = stock.yuv_to_image(Orientation::TopDown).unwrap();
+
+        let subtex = Subtexture::from_image_yuv(&image, Orientation::TopDown).unwrap();
+        assert!(subtex.is_yuv());
+        for (a, b) in subtex.mipmaps.iter().zip(&stock.mipmaps) {
+            assert_eq!((a.id(), a.width, a.height), (b.id(), b.width, b.height));
+            assert_eq!(a.data.len(), b.data.len());
+        }
+        let encoded = subtex.yuv_to_image(Orientation::TopDown).unwrap();
+        let error: u64 = encoded
+            .as_raw()
+            .iter()
+            .zip(image.as_raw())
+            .map(|(&a, &b)| (a as i64 - b as i64).unsigned_abs())
+            .sum();
+        let mean = error as f64 / image.as_raw().len() as f64;
+        assert!(mean < 2.0, "mean error {}", mean);
     }
 }